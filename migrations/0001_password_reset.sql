@@ -0,0 +1,15 @@
+ALTER TABLE sessions
+    ADD COLUMN user_id INT UNSIGNED NULL,
+    ADD INDEX sessions_user_id (user_id);
+
+-- existing sessions only have the user in their data, where values are JSON encoded strings
+UPDATE sessions SET user_id = CAST(JSON_UNQUOTE(JSON_EXTRACT(data, '$.data.user_id')) AS UNSIGNED)
+WHERE CASE WHEN JSON_VALID(data) THEN JSON_EXTRACT(data, '$.data.user_id') IS NOT NULL ELSE FALSE END;
+
+CREATE TABLE password_resets (
+    token_hash BINARY(32) PRIMARY KEY,
+    user_id INT UNSIGNED NOT NULL,
+    expiry DATETIME NOT NULL,
+    used BOOLEAN NOT NULL DEFAULT FALSE,
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+CREATE TABLE login_failures (
+    kind VARCHAR(8) NOT NULL,
+    key_value VARCHAR(128) NOT NULL,
+    failures INT UNSIGNED NOT NULL DEFAULT 0,
+    last_failure DATETIME NOT NULL,
+    locked_until DATETIME NULL,
+    PRIMARY KEY (kind, key_value)
+);
+
+-- security events (failed logins, lockouts) are not done by any logged in user
+ALTER TABLE audit_log MODIFY user_id INT UNSIGNED NULL;
@@ -0,0 +1,10 @@
+-- raw hash and salt columns are kept until every user has logged in once and been rehashed
+ALTER TABLE users
+    ADD COLUMN password_hash VARCHAR(255) NULL,
+    MODIFY credentials BINARY(64) NULL,
+    MODIFY salt BINARY(48) NULL;
+
+ALTER TABLE totp_recovery_codes
+    ADD COLUMN code_hash VARCHAR(255) NULL,
+    MODIFY credentials BINARY(64) NULL,
+    MODIFY salt BINARY(48) NULL;
@@ -0,0 +1,12 @@
+CREATE TABLE api_tokens (
+    token_id INT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    user_id INT UNSIGNED NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    scope VARCHAR(8) NOT NULL,
+    token_hash BINARY(32) NOT NULL UNIQUE,
+    created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- NULL never expires; NOW() + INTERVAL NULL DAY is NULL
+    expiry DATETIME NULL,
+    last_used DATETIME NULL,
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
@@ -0,0 +1,6 @@
+ALTER TABLE sessions
+    ADD COLUMN session_no INT UNSIGNED NOT NULL AUTO_INCREMENT UNIQUE,
+    ADD COLUMN created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN last_seen DATETIME NULL,
+    ADD COLUMN ip VARCHAR(45) NULL,
+    ADD COLUMN user_agent VARCHAR(255) NULL;
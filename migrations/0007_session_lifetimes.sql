@@ -0,0 +1,3 @@
+ALTER TABLE sessions
+    ADD COLUMN remember BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD INDEX sessions_expiry (expiry);
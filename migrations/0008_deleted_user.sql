@@ -0,0 +1,2 @@
+-- takes over the posts of deleted accounts; no password is set so it cannot be logged into
+INSERT INTO users(username, description, profile_tag) VALUES ('[deleted]', '', '');
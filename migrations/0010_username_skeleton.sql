@@ -0,0 +1,4 @@
+-- filled in for existing users at startup by `validation::backfill_skeletons`
+ALTER TABLE users
+    ADD COLUMN username_skeleton VARCHAR(255) NULL,
+    ADD UNIQUE INDEX users_username_skeleton (username_skeleton);
@@ -0,0 +1,6 @@
+-- recent signups per IP, raising the registration proof of work difficulty
+CREATE TABLE signups (
+    ip VARCHAR(64) NOT NULL,
+    created DATETIME NOT NULL,
+    INDEX signups_ip (ip, created)
+);
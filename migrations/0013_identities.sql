@@ -0,0 +1,12 @@
+-- accounts at an OpenID Connect provider that can log in as a user
+CREATE TABLE user_identities (
+    identity_id INT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    user_id INT UNSIGNED NOT NULL,
+    issuer VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    -- as given by the provider when linked, for telling identities apart
+    email VARCHAR(254) NULL,
+    created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE INDEX user_identities_subject (issuer, subject),
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
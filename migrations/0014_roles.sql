@@ -0,0 +1,37 @@
+-- which permissions each role has, see `models::Permission`
+CREATE TABLE role_permissions (
+    role VARCHAR(16) NOT NULL,
+    permission VARCHAR(32) NOT NULL,
+    PRIMARY KEY (role, permission)
+);
+
+INSERT INTO role_permissions(role, permission) VALUES
+    ('member', 'create_thread'),
+    ('member', 'create_post'),
+    ('member', 'react'),
+    ('forum_moderator', 'moderate_posts'),
+    ('global_moderator', 'moderate_posts'),
+    ('admin', 'moderate_posts'),
+    ('admin', 'manage_containers'),
+    ('admin', 'manage_users'),
+    ('admin', 'manage_roles'),
+    ('admin', 'view_logs');
+
+-- roles given to users, `member` and `guest` are implied and never stored
+-- NULLs never collide in a unique key, so `roles::role_grant` keeps rows unique instead
+CREATE TABLE user_roles (
+    role_id INT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    user_id INT UNSIGNED NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    -- only for forum_moderator
+    forum_id INT UNSIGNED NULL,
+    INDEX user_roles_user_role (user_id, role),
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (forum_id) REFERENCES forums(forum_id) ON DELETE CASCADE
+);
+
+INSERT INTO user_roles(user_id, role) SELECT user_id, 'admin' FROM users WHERE is_admin;
+
+-- users.is_admin is kept in step with the admin role for displaying, permissions come from the tables above.
+-- The older trigger limiting audit_log inserts to admins would now fail every moderator action.
+DROP TRIGGER IF EXISTS audit_log_admin_only;
@@ -0,0 +1,15 @@
+-- moderators of a whole category, covering every forum, topic and thread in it
+ALTER TABLE user_roles
+    ADD COLUMN category_id INT UNSIGNED NULL AFTER role,
+    ADD FOREIGN KEY (category_id) REFERENCES categories(category_id) ON DELETE CASCADE;
+
+INSERT INTO role_permissions(role, permission) VALUES
+    ('category_moderator', 'moderate_posts'),
+    ('category_moderator', 'lock_threads'),
+    ('category_moderator', 'move_threads'),
+    ('forum_moderator', 'lock_threads'),
+    ('forum_moderator', 'move_threads'),
+    ('global_moderator', 'lock_threads'),
+    ('global_moderator', 'move_threads'),
+    ('admin', 'lock_threads'),
+    ('admin', 'move_threads');
@@ -0,0 +1,49 @@
+-- `public`, `members` (logged in) or `restricted` (only roles listed in the *_access tables)
+ALTER TABLE categories ADD COLUMN visibility VARCHAR(10) NOT NULL DEFAULT 'public';
+ALTER TABLE forums ADD COLUMN visibility VARCHAR(10) NOT NULL DEFAULT 'public';
+
+CREATE TABLE category_access (
+    category_id INT UNSIGNED NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    PRIMARY KEY (category_id, role),
+    FOREIGN KEY (category_id) REFERENCES categories(category_id) ON DELETE CASCADE
+);
+
+CREATE TABLE forum_access (
+    forum_id INT UNSIGNED NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    PRIMARY KEY (forum_id, role),
+    FOREIGN KEY (forum_id) REFERENCES forums(forum_id) ON DELETE CASCADE
+);
+
+-- Every read goes through these so hidden content cannot show up anywhere.
+-- uid is NULL for guests. Admins see everything.
+-- Scoped roles only open up the container they are held in.
+CREATE FUNCTION can_read_category(uid INT UNSIGNED, cid INT UNSIGNED) RETURNS BOOLEAN READS SQL DATA
+RETURN EXISTS(SELECT * FROM user_roles WHERE user_id = uid AND role = 'admin')
+    OR EXISTS(
+        SELECT * FROM categories c WHERE c.category_id = cid AND (
+            c.visibility = 'public'
+            OR (c.visibility = 'members' AND uid IS NOT NULL)
+            OR (c.visibility = 'restricted' AND EXISTS(
+                SELECT * FROM category_access a INNER JOIN user_roles ur USING (role)
+                WHERE a.category_id = cid AND ur.user_id = uid
+                AND ((ur.category_id IS NULL AND ur.forum_id IS NULL) OR ur.category_id = cid)
+            ))
+        )
+    );
+
+CREATE FUNCTION can_read_forum(uid INT UNSIGNED, fid INT UNSIGNED) RETURNS BOOLEAN READS SQL DATA
+RETURN EXISTS(SELECT * FROM user_roles WHERE user_id = uid AND role = 'admin')
+    OR EXISTS(
+        SELECT * FROM forums f WHERE f.forum_id = fid AND can_read_category(uid, f.category_id) AND (
+            f.visibility = 'public'
+            OR (f.visibility = 'members' AND uid IS NOT NULL)
+            OR (f.visibility = 'restricted' AND EXISTS(
+                SELECT * FROM forum_access a INNER JOIN user_roles ur USING (role)
+                WHERE a.forum_id = fid AND ur.user_id = uid
+                AND ((ur.category_id IS NULL AND ur.forum_id IS NULL)
+                     OR ur.category_id = f.category_id OR ur.forum_id = fid)
+            ))
+        )
+    );
@@ -0,0 +1,21 @@
+CREATE TABLE bans (
+    ban_id INT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    user_id INT UNSIGNED NOT NULL,
+    -- NULL bans everywhere
+    forum_id INT UNSIGNED NULL,
+    reason VARCHAR(512) NOT NULL,
+    created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- NULL is permanent
+    expiry DATETIME NULL,
+    banned_by INT UNSIGNED NULL,
+    INDEX bans_user (user_id, expiry),
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (forum_id) REFERENCES forums(forum_id) ON DELETE CASCADE,
+    FOREIGN KEY (banned_by) REFERENCES users(user_id) ON DELETE SET NULL
+);
+
+INSERT INTO role_permissions(role, permission) VALUES
+    ('forum_moderator', 'ban_users'),
+    ('category_moderator', 'ban_users'),
+    ('global_moderator', 'ban_users'),
+    ('admin', 'ban_users');
@@ -0,0 +1,6 @@
+-- locked threads take no replies, pinned ones sort first in their topic,
+-- archived ones are read only, taking neither replies nor reactions
+ALTER TABLE threads
+    ADD COLUMN locked BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,13 @@
+ALTER TABLE posts ADD COLUMN edited DATETIME NULL;
+
+-- every version of a post before an edit, by who replaced it
+CREATE TABLE post_revisions (
+    revision_id INT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    post_id INT UNSIGNED NOT NULL,
+    editor_id INT UNSIGNED NOT NULL,
+    content TEXT NOT NULL,
+    time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX post_revisions_post (post_id, revision_id),
+    FOREIGN KEY (post_id) REFERENCES posts(post_id) ON DELETE CASCADE,
+    FOREIGN KEY (editor_id) REFERENCES users(user_id)
+);
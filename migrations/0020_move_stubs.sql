@@ -0,0 +1,20 @@
+-- left behind in the old parent when a thread or topic is moved, linking to where it went
+CREATE TABLE thread_stubs (
+    -- moved out of
+    topic_id INT UNSIGNED NOT NULL,
+    thread_id INT UNSIGNED NOT NULL,
+    created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (topic_id, thread_id),
+    FOREIGN KEY (topic_id) REFERENCES topics(topic_id) ON DELETE CASCADE,
+    FOREIGN KEY (thread_id) REFERENCES threads(thread_id) ON DELETE CASCADE
+);
+
+CREATE TABLE topic_stubs (
+    -- moved out of
+    forum_id INT UNSIGNED NOT NULL,
+    topic_id INT UNSIGNED NOT NULL,
+    created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (forum_id, topic_id),
+    FOREIGN KEY (forum_id) REFERENCES forums(forum_id) ON DELETE CASCADE,
+    FOREIGN KEY (topic_id) REFERENCES topics(topic_id) ON DELETE CASCADE
+);
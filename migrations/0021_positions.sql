@@ -0,0 +1,9 @@
+-- display order among siblings, lowest first
+ALTER TABLE categories ADD COLUMN position INT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE forums ADD COLUMN position INT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE topics ADD COLUMN position INT UNSIGNED NOT NULL DEFAULT 0;
+
+-- keeps the order from before, which was by id
+UPDATE categories SET position = category_id;
+UPDATE forums SET position = forum_id;
+UPDATE topics SET position = topic_id;
@@ -0,0 +1,62 @@
+-- deleted rows stay hidden until restored or purged, see `utils::trash`
+ALTER TABLE categories
+    ADD COLUMN deleted DATETIME NULL,
+    ADD COLUMN deleted_by INT UNSIGNED NULL,
+    ADD FOREIGN KEY (deleted_by) REFERENCES users(user_id) ON DELETE SET NULL;
+ALTER TABLE forums
+    ADD COLUMN deleted DATETIME NULL,
+    ADD COLUMN deleted_by INT UNSIGNED NULL,
+    ADD FOREIGN KEY (deleted_by) REFERENCES users(user_id) ON DELETE SET NULL;
+ALTER TABLE topics
+    ADD COLUMN deleted DATETIME NULL,
+    ADD COLUMN deleted_by INT UNSIGNED NULL,
+    ADD FOREIGN KEY (deleted_by) REFERENCES users(user_id) ON DELETE SET NULL;
+ALTER TABLE threads
+    ADD COLUMN deleted DATETIME NULL,
+    ADD COLUMN deleted_by INT UNSIGNED NULL,
+    ADD FOREIGN KEY (deleted_by) REFERENCES users(user_id) ON DELETE SET NULL;
+ALTER TABLE posts
+    ADD COLUMN deleted DATETIME NULL,
+    ADD COLUMN deleted_by INT UNSIGNED NULL,
+    ADD FOREIGN KEY (deleted_by) REFERENCES users(user_id) ON DELETE SET NULL;
+
+-- deleted categories and forums cannot be read by anyone, admins included,
+-- which hides everything in them from every read filtered by these
+DROP FUNCTION can_read_forum;
+DROP FUNCTION can_read_category;
+
+CREATE FUNCTION can_read_category(uid INT UNSIGNED, cid INT UNSIGNED) RETURNS BOOLEAN READS SQL DATA
+RETURN EXISTS(SELECT * FROM categories WHERE category_id = cid AND deleted IS NULL) AND (
+    EXISTS(SELECT * FROM user_roles WHERE user_id = uid AND role = 'admin')
+    OR EXISTS(
+        SELECT * FROM categories c WHERE c.category_id = cid AND (
+            c.visibility = 'public'
+            OR (c.visibility = 'members' AND uid IS NOT NULL)
+            OR (c.visibility = 'restricted' AND EXISTS(
+                SELECT * FROM category_access a INNER JOIN user_roles ur USING (role)
+                WHERE a.category_id = cid AND ur.user_id = uid
+                AND ((ur.category_id IS NULL AND ur.forum_id IS NULL) OR ur.category_id = cid)
+            ))
+        )
+    )
+);
+
+CREATE FUNCTION can_read_forum(uid INT UNSIGNED, fid INT UNSIGNED) RETURNS BOOLEAN READS SQL DATA
+RETURN EXISTS(
+    SELECT * FROM forums f INNER JOIN categories c USING (category_id)
+    WHERE f.forum_id = fid AND f.deleted IS NULL AND c.deleted IS NULL
+) AND (
+    EXISTS(SELECT * FROM user_roles WHERE user_id = uid AND role = 'admin')
+    OR EXISTS(
+        SELECT * FROM forums f WHERE f.forum_id = fid AND can_read_category(uid, f.category_id) AND (
+            f.visibility = 'public'
+            OR (f.visibility = 'members' AND uid IS NOT NULL)
+            OR (f.visibility = 'restricted' AND EXISTS(
+                SELECT * FROM forum_access a INNER JOIN user_roles ur USING (role)
+                WHERE a.forum_id = fid AND ur.user_id = uid
+                AND ((ur.category_id IS NULL AND ur.forum_id IS NULL)
+                     OR ur.category_id = f.category_id OR ur.forum_id = fid)
+            ))
+        )
+    )
+);
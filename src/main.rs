@@ -3,6 +3,7 @@ mod utils;
 mod middleware;
 mod models;
 
+use std::sync::Arc;
 use async_std;
 use sqlx::mysql::MySqlPoolOptions;
 use tide::http::headers::HeaderValue;
@@ -10,7 +11,8 @@ use tide::log;
 
 #[derive(Clone)]
 pub struct State {
-    db: sqlx::mysql::MySqlPool
+    db: sqlx::mysql::MySqlPool,
+    mailer: Arc<dyn utils::mail::Mailer>
 }
 
 pub type Request = tide::Request<State>;
@@ -26,15 +28,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .max_connections(5)
             .connect(&std::env::var("DATABASE_URL")?).await?;
         log::debug!("Database connected");
-        let mut app = tide::with_state(State { db: pool.clone() });
+        utils::validation::backfill_skeletons(&pool).await?;
+        async_std::task::spawn(utils::sessions::sweep_expired(
+            pool.clone(),
+            std::time::Duration::from_secs(utils::env_or("SESSION_SWEEP_SECS", 60 * 60))
+        ));
+        async_std::task::spawn(utils::export::sweep_expired(
+            pool.clone(),
+            std::time::Duration::from_secs(utils::env_or("EXPORT_SWEEP_SECS", 60 * 60))
+        ));
+        async_std::task::spawn(utils::trash::sweep_expired(
+            pool.clone(),
+            std::time::Duration::from_secs(utils::env_or("TRASH_SWEEP_SECS", 60 * 60))
+        ));
+        let mut app = tide::with_state(State {
+            db: pool.clone(),
+            mailer: utils::mail::from_env()
+        });
         routes::add_routes(
             &mut app
                 .with(middleware::ErrorHandleMiddleware {})
                 .with(log::LogMiddleware::new())
                 .with(tide::security::CorsMiddleware::new()
                     .allow_credentials(true)
-                    .allow_headers("Content-Type".parse::<HeaderValue>().unwrap())
-                    .allow_methods("DELETE, GET, PATCH, POST, OPTIONS".parse::<HeaderValue>().unwrap())
+                    .allow_headers(format!("Content-Type, Authorization, {}", middleware::CSRF_HEADER)
+                        .parse::<HeaderValue>().unwrap())
+                    .allow_methods("DELETE, GET, PATCH, POST, PUT, OPTIONS".parse::<HeaderValue>().unwrap())
                     .expose_headers("Content-Encoding".parse::<HeaderValue>().unwrap())
                     .allow_origin("http://localhost:1212")),
             r#".\images\"#
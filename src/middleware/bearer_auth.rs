@@ -0,0 +1,56 @@
+use tide::{StatusCode, http::Method, sessions::Session};
+
+use crate::{State, models::Scope, utils::{auth, permissions}};
+
+/// Lets requests with `Authorization: Bearer <token>` act as the token's owner.
+/// Must be added after the session middleware.
+pub(crate) struct BearerAuthMiddleware;
+
+/// The scope a token needs for a request, or `None` if tokens cannot be used for it at all.
+fn required_scope(method: Method, path: &str) -> Option<Scope> {
+    if path.starts_with("/api/auth")
+        || (path.starts_with("/api/users/") && (path.contains("/tokens") || path.contains("/sessions")
+            || path.contains("/export") || path.contains("/identities") || path.contains("/roles"))) {
+        return None
+    }
+    match method {
+        Method::Get | Method::Head | Method::Options => Some(Scope::Read),
+        _ if path.starts_with("/api/posts") || path.starts_with("/api/threads") => Some(Scope::Post),
+        _ => Some(Scope::Write)
+    }
+}
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for BearerAuthMiddleware {
+    async fn handle(&self, mut request: crate::Request, next: tide::Next<'_, State>) -> tide::Result {
+        let token = match request.header("Authorization")
+            .and_then(|h| h.last().as_str().strip_prefix("Bearer ").map(|t| t.trim().to_string())) {
+            Some(t) => t,
+            None => return Ok(next.run(request).await)
+        };
+        let required = match required_scope(request.method(), request.url().path()) {
+            Some(s) => s,
+            None => return Ok(permissions::forbidden("not available with api tokens"))
+        };
+        let rec = match sqlx::query!(
+            "SELECT token_id, user_id, scope FROM api_tokens
+             WHERE token_hash = ? AND (expiry IS NULL OR expiry > NOW())",
+            auth::token_digest(&token)
+        ).fetch_optional(&request.state().db).await? {
+            Some(r) => r,
+            None => return Ok(permissions::denied(StatusCode::Unauthorized, "invalid or expired token"))
+        };
+        if Scope::parse(&rec.scope).map_or(true, |s| s < required) {
+            return Ok(permissions::forbidden(&format!("token lacks scope `{}`", required.as_str())))
+        }
+        sqlx::query!("UPDATE api_tokens SET last_used = NOW() WHERE token_id = ?", rec.token_id)
+            .execute(&request.state().db).await?;
+
+        // a fresh session that is never stored, so a token cannot be turned into a cookie
+        // and any cookie session sent alongside is left alone
+        let mut session = Session::new();
+        session.insert("user_id", rec.user_id)?;
+        request.set_ext(session);
+        Ok(next.run(request).await)
+    }
+}
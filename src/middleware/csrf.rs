@@ -0,0 +1,54 @@
+use tide::{Response, StatusCode, http::Method, sessions::Session};
+
+use crate::{Request, State, utils::{auth, permissions}};
+
+pub(crate) const CSRF_HEADER: &str = "X-CSRF-Token";
+const CSRF_KEY: &str = "csrf_token";
+
+/// Requires every mutating request to echo the session's CSRF token in `X-CSRF-Token`.
+/// Requests authenticated with an api token are exempt, browsers never send those by themselves.
+/// Must be added after the session middleware.
+pub(crate) struct CsrfMiddleware;
+
+/// Compares without stopping at the first difference, so the token cannot be guessed byte by byte.
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Drops the token so a new one is issued, for when the session changes hands.
+pub(crate) fn reset_token(sess: &mut Session) {
+    sess.remove(CSRF_KEY);
+}
+
+/// Gives the session's token, issuing one if it has none yet.
+/// Needs fetching again after logging in or registering.
+pub async fn csrf_token(mut req: Request) -> tide::Result {
+    let sess = req.session_mut();
+    let token = match sess.get::<String>(CSRF_KEY) {
+        Some(t) => t,
+        None => {
+            let t = auth::new_token();
+            sess.insert(CSRF_KEY, &t)?;
+            t
+        }
+    };
+    Ok(Response::builder(StatusCode::Ok)
+        .body(tide::convert::json!({ "token": token }))
+        .build())
+}
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for CsrfMiddleware {
+    async fn handle(&self, request: Request, next: tide::Next<'_, State>) -> tide::Result {
+        if matches!(request.method(), Method::Get | Method::Head | Method::Options)
+            || request.header("Authorization").map_or(false, |h| h.last().as_str().starts_with("Bearer ")) {
+            return Ok(next.run(request).await)
+        }
+        let expected = request.session().get::<String>(CSRF_KEY);
+        let given = request.header(CSRF_HEADER).map(|h| h.last().as_str());
+        match (expected, given) {
+            (Some(e), Some(g)) if tokens_match(e.as_bytes(), g.as_bytes()) => Ok(next.run(request).await),
+            _ => Ok(permissions::forbidden("missing or invalid csrf token, fetch one from /api/auth/csrf"))
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::{State, models::{CurrentUser, Permission}, utils::permissions::{self, Location}};
+
+/// Loads the user the session or API token is for once per request, see `permissions::current_user`.
+/// Sessions of users that no longer exist are treated as logged out.
+/// Must be added after the session and bearer auth middleware.
+pub(crate) struct CurrentUserMiddleware;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for CurrentUserMiddleware {
+    async fn handle(&self, mut request: crate::Request, next: tide::Next<'_, State>) -> tide::Result {
+        if let Some(user_id) = request.session().get::<u32>("user_id") {
+            let user = sqlx::query_as!(CurrentUser,
+                "SELECT user_id, username,
+                 EXISTS(SELECT * FROM user_roles r WHERE r.user_id = u.user_id AND role = 'admin') `is_admin: bool`
+                 FROM users u WHERE user_id = ?",
+                user_id
+            ).fetch_optional(&request.state().db).await?;
+            if let Some(user) = user {
+                request.set_ext(user);
+            }
+        }
+        Ok(next.run(request).await)
+    }
+}
+
+/// Lets only users with a permission outside any container through to a route.
+pub(crate) struct RequirePermission(pub Permission);
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for RequirePermission {
+    async fn handle(&self, request: crate::Request, next: tide::Next<'_, State>) -> tide::Result {
+        if let Err(resp) = permissions::require(&request, self.0, Location::GLOBAL).await? {
+            return Ok(resp)
+        }
+        Ok(next.run(request).await)
+    }
+}
@@ -1,3 +1,11 @@
 mod error_handle;
+mod bearer_auth;
+mod session_track;
+mod csrf;
+mod current_user;
 
 pub(crate) use error_handle::ErrorHandleMiddleware;
+pub(crate) use bearer_auth::BearerAuthMiddleware;
+pub(crate) use session_track::SessionTrackMiddleware;
+pub(crate) use csrf::{CsrfMiddleware, CSRF_HEADER, csrf_token, reset_token};
+pub(crate) use current_user::{CurrentUserMiddleware, RequirePermission};
@@ -0,0 +1,46 @@
+use tide::http::cookies::SameSite;
+
+use crate::{State, utils::{sessions, throttle}};
+
+const USER_AGENT_LEN: usize = 255;
+
+/// Keeps the IP, user agent and last seen time of stored sessions up to date,
+/// so users can see where they are logged in, and slides logged in sessions' expiry.
+/// The session middleware only sets the cookie for new sessions, so extending re-issues it here.
+/// Must be added after the session middleware.
+pub(crate) struct SessionTrackMiddleware;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for SessionTrackMiddleware {
+    async fn handle(&self, mut request: crate::Request, next: tide::Next<'_, State>) -> tide::Result {
+        let ip = throttle::client_ip(&request);
+        let user_agent: String = request.header("User-Agent")
+            .map(|h| h.last().as_str().chars().take(USER_AGENT_LEN).collect())
+            .unwrap_or_default();
+        let session = request.session_mut();
+        // an empty session is not stored, this would make every anonymous request store one
+        // unchanged values do not cause a store
+        if session.len() != 0 {
+            session.insert("ip", ip)?;
+            session.insert("user_agent", user_agent)?;
+        }
+        let sess_id = session.id().to_string();
+        let extended = match session.get::<u32>("user_id") {
+            Some(_) => sessions::extend_session(&request.state().db, &sess_id).await?,
+            None => None
+        };
+        let cookie = request.cookie(sessions::COOKIE_NAME);
+        let secure = request.url().scheme() == "https";
+        let mut response = next.run(request).await;
+        // a logout or regenerated id sets the cookie again in the session middleware, replacing this one
+        if let (Some(expiry), Some(mut cookie)) = (extended, cookie) {
+            cookie.set_path("/");
+            cookie.set_http_only(true);
+            cookie.set_same_site(SameSite::Lax);
+            cookie.set_secure(secure);
+            cookie.set_expires(expiry.map(Into::into));
+            response.insert_cookie(cookie);
+        }
+        Ok(response)
+    }
+}
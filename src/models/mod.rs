@@ -1,5 +1,13 @@
 mod generic_containers;
 mod users;
+mod tokens;
+mod roles;
+mod posts;
+mod trash;
 
 pub use generic_containers::*;
 pub use users::*;
+pub use tokens::*;
+pub use roles::*;
+pub use posts::*;
+pub use trash::*;
@@ -0,0 +1,11 @@
+use serde::Serialize;
+
+/// A previous version of a post, replaced by `editor_id` at `time`.
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct Revision {
+    pub revision_id: u32,
+    pub editor_id: u32,
+    pub editor: String,
+    pub content: String,
+    pub time: chrono::NaiveDateTime
+}
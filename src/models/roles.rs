@@ -0,0 +1,131 @@
+use serde::{Serialize, Deserialize};
+
+/// Who a user is to the forum. Roles add up, a user has every permission of each role they hold.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    GlobalModerator,
+    /// Held in one forum, given with a `forum_id`.
+    ForumModerator,
+    /// Held in one category and every forum in it, given with a `category_id`.
+    CategoryModerator,
+    /// Every logged in user, never stored.
+    Member,
+    /// Everyone, logged in or not, never stored.
+    Guest
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::GlobalModerator => "global_moderator",
+            Role::ForumModerator => "forum_moderator",
+            Role::CategoryModerator => "category_moderator",
+            Role::Member => "member",
+            Role::Guest => "guest"
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Role> {
+        match s {
+            "admin" => Some(Role::Admin),
+            "global_moderator" => Some(Role::GlobalModerator),
+            "forum_moderator" => Some(Role::ForumModerator),
+            "category_moderator" => Some(Role::CategoryModerator),
+            "member" => Some(Role::Member),
+            "guest" => Some(Role::Guest),
+            _ => None
+        }
+    }
+
+    /// Whether users are given this role, rather than having it by being logged in or not.
+    pub fn is_assignable(&self) -> bool {
+        !matches!(self, Role::Member | Role::Guest)
+    }
+}
+
+/// Something a role allows. Which roles have which is stored in `role_permissions`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    CreateThread,
+    CreatePost,
+    React,
+    /// Deleting other users' threads and posts.
+    ModeratePosts,
+    /// Locking, pinning and archiving threads.
+    LockThreads,
+    /// Moving threads to another topic.
+    MoveThreads,
+    /// Banning users, from just a forum when held in one.
+    BanUsers,
+    /// Creating, changing and deleting categories, forums and topics.
+    ManageContainers,
+    /// Requiring 2FA for, deleting, and managing the sessions of other users.
+    ManageUsers,
+    ManageRoles,
+    ViewLogs
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::CreateThread => "create_thread",
+            Permission::CreatePost => "create_post",
+            Permission::React => "react",
+            Permission::ModeratePosts => "moderate_posts",
+            Permission::LockThreads => "lock_threads",
+            Permission::MoveThreads => "move_threads",
+            Permission::BanUsers => "ban_users",
+            Permission::ManageContainers => "manage_containers",
+            Permission::ManageUsers => "manage_users",
+            Permission::ManageRoles => "manage_roles",
+            Permission::ViewLogs => "view_logs"
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct UserRole {
+    pub role: String,
+    pub category_id: Option<u32>,
+    pub forum_id: Option<u32>
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct RolePermission {
+    pub role: String,
+    pub permission: String
+}
+
+/// Who can see a category or forum. A forum is only visible if its category is too.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    Public,
+    /// Logged in users.
+    Members,
+    /// Users with one of the roles listed for it.
+    Restricted
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Members => "members",
+            Visibility::Restricted => "restricted"
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Visibility> {
+        match s {
+            "public" => Some(Visibility::Public),
+            "members" => Some(Visibility::Members),
+            "restricted" => Some(Visibility::Restricted),
+            _ => None
+        }
+    }
+}
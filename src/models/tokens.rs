@@ -0,0 +1,42 @@
+use serde::{Serialize, Deserialize};
+
+/// What a personal access token may do. Each scope includes the ones before it.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Only GET requests.
+    Read,
+    /// Creating, editing and deleting threads, posts and reactions.
+    Post,
+    /// Every `/api` route except authentication and token management.
+    Write
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Post => "post",
+            Scope::Write => "write"
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Scope> {
+        match s {
+            "read" => Some(Scope::Read),
+            "post" => Some(Scope::Post),
+            "write" => Some(Scope::Write),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct ApiToken {
+    pub token_id: u32,
+    pub name: String,
+    pub scope: String,
+    pub created: chrono::NaiveDateTime,
+    pub expiry: Option<chrono::NaiveDateTime>,
+    pub last_used: Option<chrono::NaiveDateTime>
+}
@@ -0,0 +1,14 @@
+use serde::Serialize;
+
+/// Something deleted that can still be restored.
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct TrashItem {
+    // `category`, `forum`, `topic`, `thread` or `post`
+    pub kind: &'static str,
+    pub id: u32,
+    // the start of the content for posts
+    pub name: String,
+    pub deleted: chrono::NaiveDateTime,
+    pub deleted_by: Option<u32>,
+    pub purge_after: chrono::NaiveDateTime
+}
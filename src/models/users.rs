@@ -11,6 +11,55 @@ pub struct User {
     pub is_admin: bool
 }
 
+/// The logged in user making a request, attached to it by `CurrentUserMiddleware`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CurrentUser {
+    pub user_id: u32,
+    pub username: String,
+    pub is_admin: bool
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct SessionInfo {
+    pub session_no: u32,
+    pub created: chrono::NaiveDateTime,
+    pub last_seen: Option<chrono::NaiveDateTime>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    // whether this is the session making the request
+    pub current: bool
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct DataExport {
+    pub export_id: u32,
+    // `pending`, `ready` or `failed`
+    pub status: String,
+    pub created: chrono::NaiveDateTime,
+    // when the download stops being available, set once ready
+    pub expiry: Option<chrono::NaiveDateTime>
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct LinkedIdentity {
+    pub identity_id: u32,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created: chrono::NaiveDateTime
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct Ban {
+    pub ban_id: u32,
+    // none for bans from everywhere
+    pub forum_id: Option<u32>,
+    pub reason: String,
+    pub created: chrono::NaiveDateTime,
+    // none for permanent bans
+    pub expiry: Option<chrono::NaiveDateTime>
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub struct Log {
     pub log_id: u32,
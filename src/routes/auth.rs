@@ -1,84 +1,351 @@
-use tide::{Response, StatusCode};
+use tide::{Response, StatusCode, sessions::Session};
 use serde::{Serialize, Deserialize};
 
-use crate::{Request, utils::{wrap_error, auth, sessions::SessionWorkaroundExt}};
+use crate::{Request, middleware, utils::{wrap_error, auth, bans, email, permissions, pow, throttle, validation, sessions::{self, SessionWorkaroundExt}}};
 
-const PRE_AUTH_KEY: &str = "pre_auth";
+// set between the password step and the 2FA step of a login, holds the user id
+pub(crate) const TOTP_PENDING_KEY: &str = "totp_pending";
+const RESET_TOKEN_MINUTES: u32 = 30;
 
 #[derive(Deserialize)]
 struct Login {
     username: String,
-    password: String
+    password: String,
+    // picks the long session lifetime
+    #[serde(default)]
+    remember: bool
+}
+
+#[derive(Deserialize)]
+struct Registration {
+    username: String,
+    password: String,
+    email: Option<String>,
+    // solves the challenge from `pre_auth`
+    nonce: String
 }
 
 #[derive(Serialize)]
-struct LoginResult {
-    user_id: u32,
-    is_admin: bool
+pub(crate) struct LoginResult {
+    pub user_id: u32,
+    pub is_admin: bool
+}
+
+#[derive(Serialize)]
+struct TotpPending {
+    enrolment_required: bool
+}
+
+/// The same for unknown usernames and wrong passwords, so usernames cannot be probed.
+pub(crate) fn login_failure() -> Response {
+    Response::builder(StatusCode::Forbidden).body("incorrect username or password").build()
 }
 
+pub(crate) fn locked_out() -> Response {
+    Response::builder(StatusCode::TooManyRequests)
+        .body("too many failed login attempts, try again later").build()
+}
+
+/// Turns the session into a fully logged in one.
+pub(crate) fn grant_session(sess: &mut Session, user_id: u32) -> tide::Result<()> {
+    sess.mark_for_regenerate();
+    sess.insert("user_id", user_id)?;
+    sess.remove(TOTP_PENDING_KEY);
+    middleware::reset_token(sess);
+    Ok(())
+}
+
+/// Issues the proof of work challenge `register` needs solved.
 pub async fn pre_auth(mut req: Request) -> tide::Result {
-    let sess = req.session_mut();
-    sess.insert_raw(PRE_AUTH_KEY, "_".to_string());
-    Ok(Response::new(StatusCode::Ok))
+    let ip = throttle::client_ip(&req);
+    let db = req.state().db.clone();
+    let challenge = pow::issue(&db, req.session_mut(), &ip).await?;
+    Ok(Response::builder(StatusCode::Ok).body(serde_json::to_value(challenge)?).build())
 }
 
 pub async fn register(mut req: Request) -> tide::Result {
-    if req.session().get_raw(PRE_AUTH_KEY).is_none() {
-        return Ok(Response::builder(StatusCode::Unauthorized).body("pre auth required").build())
-    }
-
-    let reg_data: Login = req.body_json().await?;
-    let hash_data = wrap_error!(auth::hash(&reg_data.password), StatusCode::InternalServerError);
-    let result = sqlx::query!(
-        "INSERT INTO users(username, credentials, salt) VALUES (?, ?, ?)",
-        reg_data.username,
-        &hash_data.hash[..],
-        &hash_data.salt[..]
-    ).execute(&req.state().db).await?;
+    let reg_data: Registration = req.body_json().await?;
+    if !pow::check(req.session_mut(), &reg_data.nonce) {
+        return Ok(Response::builder(StatusCode::Forbidden)
+            .body("proof of work missing, wrong or expired, get a new challenge from /api/auth/pre_auth").build())
+    }
+    let username = validation::normalize_username(&reg_data.username);
+    let email = reg_data.email.as_deref().map(str::trim).filter(|e| !e.is_empty());
+    let mut violations = validation::check_username(&username);
+    violations.extend(validation::check_password(&reg_data.password, &username));
+    if let Some(e) = email {
+        violations.extend(validation::check_email(e));
+    }
+    if !violations.is_empty() {
+        return validation::unprocessable(violations)
+    }
+    let skeleton = validation::username_skeleton(&username);
+    let taken = || validation::unprocessable(vec![
+        validation::Violation::new("username", "taken", "is taken or too similar to a taken username")
+    ]);
+    if sqlx::query!("SELECT 1 AS ex FROM users WHERE username_skeleton = ?", skeleton)
+        .fetch_optional(&req.state().db).await?.is_some() {
+        return taken()
+    }
+    if let Some(e) = email {
+        if sqlx::query!("SELECT 1 AS ex FROM users WHERE email = ?", e)
+            .fetch_optional(&req.state().db).await?.is_some() {
+            return validation::unprocessable(vec![
+                validation::Violation::new("email", "taken", "is already in use")
+            ])
+        }
+    }
+    pow::consume(req.session_mut());
+
+    let password_hash = wrap_error!(auth::hash(&reg_data.password), StatusCode::InternalServerError);
+    let result = match sqlx::query!(
+        "INSERT INTO users(username, username_skeleton, password_hash, email) VALUES (?, ?, ?, ?)",
+        username,
+        skeleton,
+        password_hash,
+        email
+    ).execute(&req.state().db).await {
+        Ok(r) => r,
+        // lost a race with another registration
+        Err(sqlx::Error::Database(e))
+        if e.code().map_or(false, |s| s == "23000") => return taken(),
+        Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
+    };
     let user_id = result.last_insert_id();
+    pow::record_signup(&req.state().db, &throttle::client_ip(&req)).await?;
+    if let Some(e) = email {
+        // the account exists now, failing to send is not worth failing the registration for
+        if let Err(err) = email::send_verification(req.state(), user_id as u32, e).await {
+            tide::log::error!("failed to send verification email: {:?}", err);
+        }
+    }
     let sess = req.session_mut(); // must reborrow here so it can be dropped earlier
     sess.mark_for_regenerate();
     sess.insert("user_id", user_id)?;
-    sess.remove(PRE_AUTH_KEY);
+    middleware::reset_token(sess);
     Ok(Response::builder(StatusCode::Created).body(user_id.to_string()).build())
 }
 
 pub async fn login<'a>(mut req: Request) -> tide::Result {
-    if req.session().get_raw(PRE_AUTH_KEY).is_none() {
-        return Ok(Response::builder(StatusCode::Unauthorized).body("pre auth required").build())
+    let login_data: Login = req.body_json().await?;
+    let username = validation::normalize_username(&login_data.username);
+    let ip = throttle::client_ip(&req);
+    if throttle::is_locked(&req.state().db, &username, &ip).await? {
+        return Ok(locked_out())
     }
+    let data = sqlx::query!(
+        "SELECT user_id, password_hash, credentials `creds: Vec<u8>`,
+         salt `salt: Vec<u8>`, is_admin `is_admin: bool`,
+         totp_enabled `totp_enabled: bool`, totp_required `totp_required: bool`
+         FROM users WHERE username IN (?, ?) ORDER BY username = ? DESC LIMIT 1",
+        // names from before normalizing are stored as they were typed, an exact match wins
+        &username, &login_data.username, &login_data.username
+    ).fetch_optional(&req.state().db).await?;
 
-    let login_data: Login = req.body_json().await?;
-    let data = match sqlx::query!(
-        "SELECT user_id, credentials `creds: Vec<u8>`,
-         salt `salt: Vec<u8>`, is_admin `is_admin: bool`
-         FROM users WHERE username = ?",
-        &login_data.username
-    ).fetch_optional(&req.state().db).await? {
-        Some(d) => d,
-        None => return Ok(Response::builder(StatusCode::Forbidden).body("username not found").build())
+    let result = match &data {
+        Some(d) => auth::verify_stored(
+            &login_data.password,
+            d.password_hash.as_deref(),
+            d.creds.as_deref().zip(d.salt.as_deref())
+        ),
+        None => Err(auth::verify_dummy(&login_data.password))
     };
+    let (data, outdated) = match (result, data) {
+        (Ok(outdated), Some(d)) => (d, outdated),
+        (Ok(_), None) | (Err(auth::PASSWORD_ERROR), _) => {
+            throttle::login_failed(&req.state().db, &username, &ip, "password").await?;
+            return Ok(login_failure())
+        },
+        (Err(e), _) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
+    };
+    throttle::clear(&req.state().db, throttle::Kind::Account, &username).await?;
+    if outdated {
+        // the password is only ever known here, so this is where params or pepper changes apply
+        set_password(&req.state().db, data.user_id, &login_data.password).await?;
+    }
+    if let Some(ban) = bans::permanent(&req.state().db, data.user_id).await? {
+        return Ok(bans::banned(&ban))
+    }
+
+    finish_login(
+        req.session_mut(), data.user_id, data.is_admin, data.totp_enabled, data.totp_required, login_data.remember
+    )
+}
 
-    match auth::verify(
-        login_data.password,
-        auth::Hashed::new_check_length(data.creds.as_slice(), data.salt.as_slice())
+/// Everything after the user is known to be who they say, the same however they logged in.
+pub(crate) fn finish_login(
+    sess: &mut Session, user_id: u32, is_admin: bool, totp_enabled: bool, totp_required: bool, remember: bool
+) -> tide::Result {
+    sess.insert(sessions::REMEMBER_KEY, remember)?;
+    if totp_enabled || totp_required {
+        // only half logged in until `totp::login_totp` or `totp::enrol_confirm`
+        sess.insert(TOTP_PENDING_KEY, user_id)?;
+        return Ok(Response::builder(StatusCode::Accepted)
+            .body(serde_json::to_value(TotpPending { enrolment_required: !totp_enabled })?)
+            .build())
+    }
+    grant_session(sess, user_id)?;
+    Ok(serde_json::to_value(LoginResult { user_id, is_admin })?.into())
+}
+
+pub async fn logout(mut req: Request) -> tide::Result {
+    req.session_mut().destroy();
+    Ok("".into())
+}
+
+
+async fn set_password<'c, E: sqlx::Executor<'c, Database = sqlx::MySql>>(
+    db: E, user_id: u32, password: &str
+) -> tide::Result<()> {
+    let password_hash = wrap_error!(auth::hash(password), StatusCode::InternalServerError);
+    sqlx::query!(
+        "UPDATE users SET password_hash = ?, credentials = NULL, salt = NULL WHERE user_id = ?",
+        password_hash, user_id
+    ).execute(db).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct PasswordChange {
+    old_password: String,
+    new_password: String
+}
+
+pub async fn change_password(mut req: Request) -> tide::Result {
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: PasswordChange = req.body_json().await?;
+    let creds = sqlx::query!(
+        "SELECT username, password_hash, credentials `creds: Vec<u8>`, salt `salt: Vec<u8>`
+         FROM users WHERE user_id = ?",
+        user_id
+    ).fetch_one(&req.state().db).await?;
+    match auth::verify_stored(
+        &data.old_password,
+        creds.password_hash.as_deref(),
+        creds.creds.as_deref().zip(creds.salt.as_deref())
     ) {
-        Ok(()) => (),
+        Ok(_) => (),
         Err(auth::PASSWORD_ERROR) => return Ok(
             Response::builder(StatusCode::Forbidden).body("incorrect password").build()),
         Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
     }
-    let sess = req.session_mut();
-    sess.mark_for_regenerate();
-    sess.insert("user_id", data.user_id)?;
-    sess.remove(PRE_AUTH_KEY);
-    Ok(serde_json::to_value(LoginResult {
-        user_id: data.user_id, is_admin: data.is_admin
-    })?.into())
+    let violations = validation::check_password(&data.new_password, &creds.username);
+    if !violations.is_empty() {
+        return validation::unprocessable(violations)
+    }
+    set_password(&req.state().db, user_id, &data.new_password).await?;
+    // everywhere else is logged out, this session stays
+    sessions::clear_user_sessions(&req.state().db, user_id, Some(req.session().id())).await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
-pub async fn logout(mut req: Request) -> tide::Result {
-    req.session_mut().destroy();
-    Ok("".into())
+#[derive(Deserialize)]
+struct ResetRequest {
+    username: String
+}
+
+pub async fn reset_request(mut req: Request) -> tide::Result {
+    let data: ResetRequest = req.body_json().await?;
+    // only verified addresses are sent to, otherwise anyone could have set the address
+    if let Some(r) = sqlx::query!(
+        "SELECT user_id, email `email!` FROM users
+         WHERE username IN (?, ?) AND email IS NOT NULL AND email_verified
+         ORDER BY username = ? DESC LIMIT 1",
+        validation::normalize_username(&data.username), data.username, data.username
+    ).fetch_optional(&req.state().db).await? {
+        let token = auth::new_token();
+        sqlx::query!(
+            "INSERT INTO password_resets(token_hash, user_id, expiry)
+             VALUES (?, ?, NOW() + INTERVAL ? MINUTE)",
+            auth::token_digest(&token), r.user_id, RESET_TOKEN_MINUTES
+        ).execute(&req.state().db).await?;
+        // a failed send is not reported, a 500 only for existing users would give them away
+        if let Err(e) = req.state().mailer.send(
+            &r.email,
+            "Password reset",
+            &format!("Use this token to reset your password: {}\nIt expires in {} minutes.",
+                     token, RESET_TOKEN_MINUTES)
+        ).await {
+            tide::log::error!("failed to send password reset email: {:?}", e);
+        }
+    }
+    // same response either way so this cannot be used to find usernames
+    Ok(Response::new(StatusCode::Accepted))
+}
+
+#[derive(Deserialize)]
+struct ResetConfirm {
+    token: String,
+    password: String
+}
+
+pub async fn reset_confirm(mut req: Request) -> tide::Result {
+    let data: ResetConfirm = req.body_json().await?;
+    let digest = auth::token_digest(&data.token);
+    let mut tx = req.state().db.begin().await?;
+    let (user_id, username) = match sqlx::query!(
+        "SELECT user_id, username FROM password_resets INNER JOIN users USING (user_id)
+         WHERE token_hash = ? AND NOT used AND expiry > NOW() FOR UPDATE",
+        digest
+    ).fetch_optional(&mut tx).await? {
+        Some(r) => (r.user_id, r.username),
+        None => return Ok(
+            Response::builder(StatusCode::Forbidden).body("invalid or expired token").build())
+    };
+    let violations = validation::check_password(&data.password, &username);
+    if !violations.is_empty() {
+        return validation::unprocessable(violations)
+    }
+    sqlx::query!("UPDATE password_resets SET used = TRUE WHERE token_hash = ?", digest)
+        .execute(&mut tx).await?;
+    set_password(&mut tx, user_id, &data.password).await?;
+    tx.commit().await?;
+    sessions::clear_user_sessions(&req.state().db, user_id, None).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[derive(Deserialize)]
+struct EmailToken {
+    token: String
+}
+
+/// Works without being logged in, so the link can be opened anywhere.
+pub async fn verify_email(mut req: Request) -> tide::Result {
+    let data: EmailToken = req.body_json().await?;
+    if let Some((user_id, email)) = email::check_token(&data.token) {
+        // the address may have changed since the token was sent
+        if sqlx::query!(
+            "UPDATE users SET email_verified = TRUE WHERE user_id = ? AND email = ?", user_id, email
+        ).execute(&req.state().db).await?.rows_affected() != 0 {
+            return Ok(Response::new(StatusCode::NoContent))
+        }
+        if sqlx::query!(
+            "SELECT 1 AS ex FROM users WHERE user_id = ? AND email = ? AND email_verified", user_id, email
+        ).fetch_optional(&req.state().db).await?.is_some() {
+            return Ok(Response::new(StatusCode::NoContent))
+        }
+    }
+    Ok(Response::builder(StatusCode::Forbidden).body("invalid or expired token").build())
+}
+
+pub async fn resend_verification(req: Request) -> tide::Result {
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let r = sqlx::query!(
+        "SELECT email, email_verified `email_verified: bool` FROM users WHERE user_id = ?", user_id
+    ).fetch_one(&req.state().db).await?;
+    let address: String = match r.email {
+        Some(address) => address,
+        None => return Ok(Response::builder(StatusCode::BadRequest).body("no email set").build())
+    };
+    if r.email_verified {
+        return Ok(Response::builder(StatusCode::Conflict).body("email already verified").build())
+    }
+    email::send_verification(req.state(), user_id, &address).await?;
+    return Ok(Response::new(StatusCode::Accepted))
 }
@@ -0,0 +1,87 @@
+use tide::{Response, StatusCode};
+use serde::Deserialize;
+
+use crate::{Request, utils::{permissions::{self, Location}, sessions}, models::{Permission, Role}};
+
+#[derive(Deserialize)]
+struct BanCreate {
+    reason: String,
+    // banned from everywhere without one
+    forum_id: Option<u32>,
+    // permanent without one
+    hours: Option<u32>
+}
+
+pub async fn ban_create(mut req: Request) -> tide::Result {
+    let target_id = req.param("user_id")?.parse::<u32>()?;
+    let data: BanCreate = req.body_json().await?;
+    let location = match data.forum_id {
+        Some(f) => permissions::forum_location(&req.state().db, f).await?,
+        None => Location::GLOBAL
+    };
+    if data.forum_id.is_some() && location.forum_id.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    let user_id = match permissions::require(&req, Permission::BanUsers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    if sqlx::query!("SELECT 1 AS ex FROM users WHERE user_id = ?", target_id)
+        .fetch_optional(&req.state().db).await?.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    if target_id == user_id || permissions::has_role(&req.state().db, target_id, Role::Admin).await? {
+        return Ok(permissions::forbidden("this user cannot be banned"))
+    }
+    let ban_id = sqlx::query!(
+        "INSERT INTO bans(user_id, forum_id, reason, expiry, banned_by)
+         VALUES (?, ?, ?, NOW() + INTERVAL ? HOUR, ?)",
+        target_id, data.forum_id, data.reason, data.hours, user_id
+    ).execute(&req.state().db).await?.last_insert_id();
+    if data.forum_id.is_none() && data.hours.is_none() {
+        // permanently gone, so they are logged out everywhere and their tokens stop working
+        sessions::clear_user_sessions(&req.state().db, target_id, None).await?;
+        sqlx::query!("DELETE FROM api_tokens WHERE user_id = ?", target_id)
+            .execute(&req.state().db).await?;
+    }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!(
+            "Banned user with ID `{}` from {} {}: {}",
+            target_id,
+            data.forum_id.map_or("everywhere".to_string(), |f| format!("forum `{}`", f)),
+            data.hours.map_or("permanently".to_string(), |h| format!("for {} hours", h)),
+            data.reason
+        )
+    ).execute(&req.state().db).await?;
+    Ok(Response::builder(StatusCode::Created)
+        .body(serde_json::to_value(ban_id)?)
+        .build())
+}
+
+pub async fn ban_lift(req: Request) -> tide::Result {
+    let target_id = req.param("user_id")?.parse::<u32>()?;
+    let ban_id = req.param("ban_id")?.parse::<u32>()?;
+    let ban = match sqlx::query!(
+        "SELECT forum_id FROM bans WHERE ban_id = ? AND user_id = ?", ban_id, target_id
+    ).fetch_optional(&req.state().db).await? {
+        Some(b) => b,
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let location = match ban.forum_id {
+        Some(f) => permissions::forum_location(&req.state().db, f).await?,
+        None => Location::GLOBAL
+    };
+    let user_id = match permissions::require(&req, Permission::BanUsers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    // kept for the record, just no longer in force
+    sqlx::query!("UPDATE bans SET expiry = NOW() WHERE ban_id = ? AND (expiry IS NULL OR expiry > NOW())", ban_id)
+        .execute(&req.state().db).await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Lifted ban `{}` of user with ID `{}`", ban_id, target_id)
+    ).execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
@@ -3,7 +3,7 @@ use serde::Serialize;
 use tide::{Response, StatusCode};
 use async_std::stream::StreamExt;
 
-use crate::{Request, utils::{data_into_hashmap, route_get}};
+use crate::{Request, utils::{data_into_vec, route_get, permissions}};
 use crate::models::*;
 
 #[derive(Serialize)]
@@ -18,7 +18,19 @@ struct Thread {
     id: u32,
     user_id: u32,
     name: String,
-    description: String
+    description: String,
+    locked: bool,
+    pinned: bool,
+    archived: bool
+}
+
+/// Left where something was moved out of, see `thread_move` and `topic_move`.
+#[derive(Serialize)]
+struct Moved {
+    id: u32,
+    name: String,
+    // where it is now
+    to: IDContainer
 }
 
 #[derive(Serialize)]
@@ -32,9 +44,13 @@ route_get!(
         let mut s = sqlx::query!(
             "SELECT category_id p_id, c.name p_name, c.description p_descr,
             f.forum_id c_id, f.name c_name, f.description c_descr
-            FROM categories c INNER JOIN forums f USING (category_id)").fetch(&req.state().db);
+            FROM categories c INNER JOIN forums f USING (category_id)
+            WHERE can_read_forum(?, f.forum_id)
+            ORDER BY c.position, category_id, f.position, f.forum_id",
+            permissions::user_id(&req)
+        ).fetch(&req.state().db);
 
-        data_into_hashmap!(s)
+        data_into_vec!(s)
     }
 );
 
@@ -56,15 +72,18 @@ pub async fn latest_posts(req: Request) -> tide::Result {
     tide::log::debug!("latest_posts_called");
     let mut s = sqlx::query!(
         "WITH ts AS (
-            SELECT t.thread_id, name, last_pos, lp.time FROM threads t
+            SELECT t.thread_id, t.name, last_pos, lp.time FROM threads t
             INNER JOIN posts lp ON (t.thread_id = lp.thread_id AND t.last_pos = lp.post_pos)
+            INNER JOIN topics top USING (topic_id)
+            WHERE can_read_forum(?, top.forum_id) AND t.deleted IS NULL AND top.deleted IS NULL
             ORDER BY lp.time DESC LIMIT 10
         ) SELECT thread_id, name, pf.content description, p.post_id, p.user_id, p.content, p.post_pos,
         username, profile_tag, is_avatar_set AS `is_avatar_set: bool`, is_admin AS `is_admin: bool`
         FROM ts INNER JOIN posts p USING (thread_id) INNER JOIN posts pf USING (thread_id)
         INNER JOIN users u ON (p.user_id = u.user_id)
-        WHERE pf.post_pos = 1 AND p.post_pos > IF(last_pos <= 5, 0, last_pos - 5)
-        ORDER BY ts.time DESC, p.post_pos"
+        WHERE pf.post_pos = 1 AND p.post_pos > IF(last_pos <= 5, 0, last_pos - 5) AND p.deleted IS NULL
+        ORDER BY ts.time DESC, p.post_pos",
+        permissions::user_id(&req)
     ).fetch(&req.state().db);
 
     let mut threads = vec!();
@@ -123,7 +142,9 @@ route_get!(
     all_categories, req,  {
         sqlx::query_as!(
             IDContainer,
-            "SELECT category_id id, name FROM categories ORDER BY category_id"
+            "SELECT category_id id, name FROM categories WHERE can_read_category(?, category_id)
+             ORDER BY position, category_id",
+            permissions::user_id(&req)
         ).fetch_all(&req.state().db).await?
     }
 );
@@ -132,27 +153,51 @@ route_get!(
     all_forums, req,  {
         sqlx::query_as!(
             IDContainer,
-            "SELECT forum_id id, name FROM forums ORDER BY category_id, forum_id"
+            "SELECT forum_id id, f.name FROM forums f INNER JOIN categories c USING (category_id)
+             WHERE can_read_forum(?, forum_id) ORDER BY c.position, category_id, f.position, forum_id",
+            permissions::user_id(&req)
         ).fetch_all(&req.state().db).await?
     }
 );
 
+#[derive(Serialize)]
+struct ForumData {
+    #[serde(flatten)]
+    data: ContainerDataParents<1>,
+    // topics moved out of the forum
+    moved: Vec<Moved>
+}
+
 pub async fn forum_data(req: Request) -> tide::Result {
     let forum_id = req.param("forum_id")?.parse::<u32>()?;
 
     if let Some(r) = sqlx::query!(
         "SELECT category_id AS c_id, c.name AS `c_name!`, f.name AS f_name, f.description AS f_description
-         FROM forums AS f LEFT JOIN categories AS c USING (category_id) WHERE forum_id = ?",
-        forum_id
+         FROM forums AS f LEFT JOIN categories AS c USING (category_id)
+         WHERE forum_id = ? AND can_read_forum(?, forum_id)",
+        forum_id, permissions::user_id(&req)
     ).fetch_optional(&req.state().db).await? {
         let vec = sqlx::query_as!(Container,
-            "SELECT topic_id AS id, name, description FROM topics WHERE forum_id = ?",
+            "SELECT topic_id AS id, name, description FROM topics WHERE forum_id = ? AND deleted IS NULL
+             ORDER BY position, topic_id",
             forum_id
         ).fetch_all(&req.state().db).await?;
-        return Ok(serde_json::to_value(ContainerDataParents {
-            parents: [IDContainer { id: r.c_id, name: r.c_name }],
-            container: BasicContainer { name: r.f_name, description: r.f_description },
-            children: vec
+        let moved = sqlx::query!(
+            "SELECT t.topic_id id, t.name, f.forum_id to_id, f.name to_name
+             FROM topic_stubs s INNER JOIN topics t USING (topic_id)
+             INNER JOIN forums f ON (f.forum_id = t.forum_id)
+             WHERE s.forum_id = ? AND can_read_forum(?, f.forum_id) AND t.deleted IS NULL ORDER BY s.created DESC",
+            forum_id, permissions::user_id(&req)
+        ).fetch_all(&req.state().db).await?.into_iter()
+            .map(|r| Moved { id: r.id, name: r.name, to: IDContainer { id: r.to_id, name: r.to_name } })
+            .collect();
+        return Ok(serde_json::to_value(ForumData {
+            data: ContainerDataParents {
+                parents: [IDContainer { id: r.c_id, name: r.c_name }],
+                container: BasicContainer { name: r.f_name, description: r.f_description },
+                children: vec
+            },
+            moved
         })?.into());
     }
     return Ok(StatusCode::NotFound.into());
@@ -162,7 +207,11 @@ route_get!(
     all_topics, req,  {
         sqlx::query_as!(
             IDContainer,
-            "SELECT topic_id id, name FROM topics ORDER BY forum_id, topic_id"
+            "SELECT topic_id id, t.name FROM topics t
+             INNER JOIN forums f USING (forum_id) INNER JOIN categories c USING (category_id)
+             WHERE can_read_forum(?, forum_id) AND t.deleted IS NULL
+             ORDER BY c.position, category_id, f.position, forum_id, t.position, topic_id",
+            permissions::user_id(&req)
         ).fetch_all(&req.state().db).await?
     }
 );
@@ -181,8 +230,8 @@ pub async fn topic_info(req: Request) -> tide::Result {
          FROM topics AS t
          LEFT JOIN forums AS f USING (forum_id)
          LEFT JOIN categories AS c USING (category_id)
-         WHERE topic_id = ?",
-        topic_id
+         WHERE topic_id = ? AND can_read_forum(?, forum_id) AND t.deleted IS NULL",
+        topic_id, permissions::user_id(&req)
     ).fetch_optional(&req.state().db).await? {
         return Ok(serde_json::to_value(TopicInfo {
             parents: [
@@ -200,29 +249,39 @@ pub const PAGE_SIZE: u16 = 10;
 #[derive(Serialize)]
 struct TopicData {
     children: Vec<Thread>,
+    // threads moved out of the topic
+    moved: Vec<Moved>,
     users: HashMap<u32, BasicUser>
 }
 
 pub async fn topic_pages(req: Request) -> tide::Result {
     let topic_id = req.param("topic_id")?.parse::<u32>()?;
+    // hidden the same as missing, so their existence does not show
+    let location = permissions::topic_location(&req.state().db, topic_id).await?;
+    if location.forum_id.is_none()
+        || !permissions::can_read(&req.state().db, permissions::user_id(&req), location).await? {
+        return Ok(StatusCode::NotFound.into())
+    }
     let vec = sqlx::query!(
-        "SELECT t.thread_id AS id, name, u.user_id, username, p.content, is_avatar_set AS `is_avatar_set: bool`
+        "SELECT t.thread_id AS id, name, u.user_id, username, p.content, is_avatar_set AS `is_avatar_set: bool`,
+         locked `locked: bool`, pinned `pinned: bool`, archived `archived: bool`
          FROM threads t INNER JOIN posts p USING (thread_id) INNER JOIN posts pl USING (thread_id)
          INNER JOIN users u ON (u.user_id = p.user_id)
-         WHERE topic_id = ? AND p.post_pos = 1 AND pl.post_pos = last_pos
-         ORDER BY pl.time DESC LIMIT ? OFFSET ?",
+         WHERE topic_id = ? AND p.post_pos = 1 AND pl.post_pos = last_pos AND t.deleted IS NULL
+         ORDER BY pinned DESC, pl.time DESC LIMIT ? OFFSET ?",
         topic_id, PAGE_SIZE, PAGE_SIZE * (req.param("page_num")?.parse::<u16>()? - 1)
     ).fetch_all(&req.state().db).await?;
 
     if vec.len() != 0 || sqlx::query!(
-        "SELECT 1 AS ex FROM topics WHERE topic_id = ?",
+        "SELECT 1 AS ex FROM topics WHERE topic_id = ? AND deleted IS NULL",
         topic_id
     ).fetch_optional(&req.state().db).await?.is_some() {
         let mut children = vec![];
         let mut users = HashMap::new();
         for r in vec {
             children.push(Thread {
-                id: r.id, name: r.name, user_id: r.user_id, description: r.content
+                id: r.id, name: r.name, user_id: r.user_id, description: r.content,
+                locked: r.locked, pinned: r.pinned, archived: r.archived
             });
             if let Entry::Vacant(e) = users.entry(r.user_id) {
                 e.insert(BasicUser {
@@ -231,9 +290,20 @@ pub async fn topic_pages(req: Request) -> tide::Result {
                 });
             }
         }
+        let moved = sqlx::query!(
+            "SELECT th.thread_id id, th.name, t.topic_id to_id, t.name to_name
+             FROM thread_stubs s INNER JOIN threads th USING (thread_id)
+             INNER JOIN topics t ON (t.topic_id = th.topic_id)
+             WHERE s.topic_id = ? AND can_read_forum(?, t.forum_id) AND th.deleted IS NULL AND t.deleted IS NULL
+             ORDER BY s.created DESC",
+            topic_id, permissions::user_id(&req)
+        ).fetch_all(&req.state().db).await?.into_iter()
+            .map(|r| Moved { id: r.id, name: r.name, to: IDContainer { id: r.to_id, name: r.to_name } })
+            .collect();
         return Ok(serde_json::to_value(
             TopicData {
                 children,
+                moved,
                 users
             }
         )?.into())
@@ -244,7 +314,10 @@ pub async fn topic_pages(req: Request) -> tide::Result {
 #[derive(Serialize)]
 struct ThreadI {
     last_pos: u32,
-    name: String
+    name: String,
+    locked: bool,
+    pinned: bool,
+    archived: bool
 }
 
 #[derive(Serialize)]
@@ -259,12 +332,14 @@ pub async fn thread_info(req: Request) -> tide::Result {
         "SELECT category_id AS `c_id!`, c.name AS `c_name!`,
          forum_id AS `f_id!`, f.name AS `f_name!`,
          topic_id AS t_id, t.name AS `t_name!`,
-         th.name AS th_name, COALESCE(th.last_pos, 0) AS `last_pos: u32`
+         th.name AS th_name, COALESCE(th.last_pos, 0) AS `last_pos: u32`,
+         th.locked `locked: bool`, th.pinned `pinned: bool`, th.archived `archived: bool`
          FROM threads AS th
          LEFT JOIN topics AS t USING (topic_id)
          LEFT JOIN forums AS f USING (forum_id)
          LEFT JOIN categories AS c USING (category_id)
-         WHERE thread_id = ?", thread_id
+         WHERE thread_id = ? AND can_read_forum(?, forum_id) AND th.deleted IS NULL AND t.deleted IS NULL",
+         thread_id, permissions::user_id(&req)
     ).fetch_optional(&req.state().db).await? {
         return Ok(serde_json::to_value(ThreadInfo {
             parents: [
@@ -275,6 +350,9 @@ pub async fn thread_info(req: Request) -> tide::Result {
             container: ThreadI {
                 last_pos: r.last_pos,
                 name: r.th_name,
+                locked: r.locked,
+                pinned: r.pinned,
+                archived: r.archived
             }
         })?.into())
     }
@@ -292,6 +370,10 @@ struct Post {
     post_id: u32,
     user_id: u32,
     content: String,
+    // deleted posts keep their place, without their content
+    deleted: bool,
+    edited: Option<chrono::NaiveDateTime>,
+    revisions: u32,
     reactions: HashMap<String, Reaction>
 }
 
@@ -312,15 +394,24 @@ struct ThreadData {
 
 pub async fn thread_pages(req: Request) -> tide::Result {
     let thread_id = req.param("thread_id")?.parse::<u32>()?;
+    let location = permissions::thread_location(&req.state().db, thread_id).await?;
+    if location.forum_id.is_none()
+        || !permissions::can_read(&req.state().db, permissions::user_id(&req), location).await? {
+        return Ok(StatusCode::NotFound.into())
+    }
     // there is no user with id 0
-    let user_id = req.session().get::<u32>("user_id").unwrap_or(0);
+    let user_id = permissions::user_id(&req).unwrap_or(0);
     let vec = sqlx::query!("
         WITH p AS (
-            SELECT post_pos, post_id, user_id, content, username, profile_tag, is_avatar_set, is_admin
+            SELECT post_pos, post_id, user_id, IF(posts.deleted IS NULL, content, '') content,
+            posts.deleted IS NOT NULL deleted,
+            edited, username, profile_tag, is_avatar_set, is_admin,
+            (SELECT COUNT(*) FROM post_revisions pr WHERE pr.post_id = posts.post_id) revisions
             FROM posts INNER JOIN users USING (user_id)
             WHERE thread_id = ? ORDER BY post_pos LIMIT ? OFFSET ?
         )
-        SELECT post_id, user_id, content, username, profile_tag, reaction, r_count `r_count: u32`,
+        SELECT post_id, user_id, content `content!`, deleted `deleted!: bool`, edited, revisions `revisions!: u32`,
+        username, profile_tag, reaction, r_count `r_count: u32`,
         is_avatar_set `is_avatar_set: bool`, is_admin `is_admin: bool`, reacted `reacted: bool`
         FROM p LEFT JOIN
         (
@@ -341,6 +432,9 @@ pub async fn thread_pages(req: Request) -> tide::Result {
             post_id: r.post_id,
             user_id: r.user_id,
             content: r.content,
+            deleted: r.deleted,
+            edited: r.edited,
+            revisions: r.revisions,
             reactions: HashMap::new()
         };
         users.insert(r.user_id, PostUser {
@@ -366,6 +460,9 @@ pub async fn thread_pages(req: Request) -> tide::Result {
                     post_id: r.post_id,
                     user_id: r.user_id,
                     content: r.content,
+                    deleted: r.deleted,
+                    edited: r.edited,
+                    revisions: r.revisions,
                     reactions: HashMap::new()
                 };
                 if let Some(react) = r.reaction {
@@ -399,3 +496,61 @@ pub async fn thread_pages(req: Request) -> tide::Result {
     }
     Ok(StatusCode::NotFound.into())
 }
+
+/// Previous versions of a post, oldest first.
+pub async fn revision_list(req: Request) -> tide::Result {
+    let post_id = req.param("post_id")?.parse::<u32>()?;
+    let location = permissions::post_location(&req.state().db, post_id).await?;
+    if location.forum_id.is_none()
+        || !permissions::can_read(&req.state().db, permissions::user_id(&req), location).await? {
+        return Ok(StatusCode::NotFound.into())
+    }
+    let data = sqlx::query_as!(Revision,
+        "SELECT revision_id, editor_id, username editor, content, time
+         FROM post_revisions INNER JOIN users ON (user_id = editor_id)
+         WHERE post_id = ? ORDER BY revision_id",
+        post_id
+    ).fetch_all(&req.state().db).await?;
+    Ok(serde_json::to_value(data)?.into())
+}
+
+#[derive(Serialize)]
+struct RevisionDiff {
+    from: u32,
+    // `None` for the current content
+    to: Option<u32>,
+    // unified diff by line
+    diff: String
+}
+
+/// Diffs a revision against the version that replaced it.
+pub async fn revision_diff(req: Request) -> tide::Result {
+    let post_id = req.param("post_id")?.parse::<u32>()?;
+    let revision_id = req.param("revision_id")?.parse::<u32>()?;
+    let location = permissions::post_location(&req.state().db, post_id).await?;
+    if location.forum_id.is_none()
+        || !permissions::can_read(&req.state().db, permissions::user_id(&req), location).await? {
+        return Ok(StatusCode::NotFound.into())
+    }
+    let old = match sqlx::query!(
+        "SELECT content FROM post_revisions WHERE post_id = ? AND revision_id = ?", post_id, revision_id
+    ).fetch_optional(&req.state().db).await? {
+        Some(r) => r.content,
+        None => return Ok(StatusCode::NotFound.into())
+    };
+    let (to, new) = match sqlx::query!(
+        "SELECT revision_id, content FROM post_revisions WHERE post_id = ? AND revision_id > ?
+         ORDER BY revision_id LIMIT 1",
+        post_id, revision_id
+    ).fetch_optional(&req.state().db).await? {
+        Some(r) => (Some(r.revision_id), r.content),
+        None => (None, sqlx::query!("SELECT content FROM posts WHERE post_id = ?", post_id)
+            .fetch_one(&req.state().db).await?.content)
+    };
+    let to_name = to.map_or("current".to_string(), |t| format!("revision {}", t));
+    let diff = similar::TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(&format!("revision {}", revision_id), &to_name)
+        .to_string();
+    Ok(serde_json::to_value(RevisionDiff { from: revision_id, to, diff })?.into())
+}
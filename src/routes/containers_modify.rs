@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
-use sqlx::{Executor, Row};
+use sqlx::{MySql, Row, Transaction};
 use tide::{Response, StatusCode};
 use crate::models::BasicContainer;
-use crate::Request;
+use crate::{Request, models::{Permission, Role, Visibility}, utils::{bans, email, permissions::{self, Location}, validation}};
 use crate::routes::containers::PAGE_SIZE;
 
 #[derive(Debug, Eq, PartialEq, Clone, Deserialize)]
@@ -12,79 +12,128 @@ struct CategoryCreate {
 }
 
 pub async fn category_create(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let data: CategoryCreate = req.body_json().await?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("create category `{}`", data.name)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-                let category_id = sqlx::query!(
-                    "INSERT INTO categories(name, description) VALUES(?, ?)",
-                    data.name, data.description
-                ).execute(&req.state().db).await?.last_insert_id();
-
-                Ok(Response::builder(StatusCode::Created).
-                    body(serde_json::to_value(category_id)?)
-                    .build())
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            },
-            Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
-    }
+    let user_id = match permissions::guarded(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: CategoryCreate = req.body_json().await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("create category `{}`", data.name)
+    ).execute(&req.state().db).await?;
+    // new ones go last
+    let category_id = sqlx::query!(
+        "INSERT INTO categories(name, description, position)
+         SELECT ?, ?, COALESCE(MAX(position), 0) + 1 FROM categories",
+        data.name, data.description
+    ).execute(&req.state().db).await?.last_insert_id();
+
+    Ok(Response::builder(StatusCode::Created).
+        body(serde_json::to_value(category_id)?)
+        .build())
 }
 
 pub async fn category_patch(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let category_id: u32 = req.param("category_id")?.parse()?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("Update category with ID `{}`", category_id)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-                let new: BasicContainer = req.body_json().await?;
-                sqlx::query!("UPDATE categories SET name = ?, description = ? WHERE category_id = ?",
-                    new.name, new.description, category_id)
-                    .execute(&req.state().db).await?;
-                Ok(Response::new(StatusCode::NoContent))
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            },
-            Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
-    }
+    let category_id: u32 = req.param("category_id")?.parse()?;
+    let location = Location::category(category_id);
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let new: BasicContainer = req.body_json().await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Update category with ID `{}`", category_id)
+    ).execute(&req.state().db).await?;
+    sqlx::query!("UPDATE categories SET name = ?, description = ? WHERE category_id = ?",
+        new.name, new.description, category_id)
+        .execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 pub async fn category_delete(req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let category_id: u32 = req.param("category_id")?.parse()?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("Delete category with ID `{}`", category_id)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-                sqlx::query!("DELETE FROM categories WHERE category_id = ?", category_id)
-                    .execute(&req.state().db).await?;
-                Ok(Response::new(StatusCode::NoContent))
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            },
-            Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+    let category_id: u32 = req.param("category_id")?.parse()?;
+    let location = Location::category(category_id);
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    if sqlx::query!(
+        "UPDATE categories SET deleted = NOW(), deleted_by = ? WHERE category_id = ? AND deleted IS NULL",
+        user_id, category_id
+    ).execute(&req.state().db).await?.rows_affected() == 0 {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Delete category with ID `{}`", category_id)
+    ).execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Access {
+    visibility: Visibility,
+    // who can see it when restricted
+    #[serde(default)]
+    roles: Vec<Role>
+}
+
+fn invalid_access(access: &Access) -> Option<Response> {
+    if access.roles.iter().any(|r| !r.is_assignable()) {
+        return Some(Response::builder(StatusCode::BadRequest)
+            .body("only roles that are given to users can be listed, use `members` visibility instead").build())
+    }
+    None
+}
+
+pub async fn category_access_get(req: Request) -> tide::Result {
+    let category_id: u32 = req.param("category_id")?.parse()?;
+    let location = Location::category(category_id);
+    if let Err(resp) = permissions::require(&req, Permission::ManageContainers, location).await? {
+        return Ok(resp)
+    }
+    let visibility = match sqlx::query!("SELECT visibility FROM categories WHERE category_id = ?", category_id)
+        .fetch_optional(&req.state().db).await? {
+        Some(r) => Visibility::parse(&r.visibility).unwrap_or(Visibility::Restricted),
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let roles = sqlx::query!("SELECT role FROM category_access WHERE category_id = ?", category_id)
+        .fetch_all(&req.state().db).await?
+        .into_iter().filter_map(|r| Role::parse(&r.role)).collect();
+    Ok(serde_json::to_value(Access { visibility, roles })?.into())
+}
+
+pub async fn category_access_put(mut req: Request) -> tide::Result {
+    let category_id: u32 = req.param("category_id")?.parse()?;
+    let location = Location::category(category_id);
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: Access = req.body_json().await?;
+    if let Some(r) = invalid_access(&data) {
+        return Ok(r)
+    }
+    if sqlx::query!("SELECT 1 AS ex FROM categories WHERE category_id = ? AND deleted IS NULL", category_id)
+        .fetch_optional(&req.state().db).await?.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    let mut tx = req.state().db.begin().await?;
+    sqlx::query!("UPDATE categories SET visibility = ? WHERE category_id = ?", data.visibility.as_str(), category_id)
+        .execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM category_access WHERE category_id = ?", category_id)
+        .execute(&mut tx).await?;
+    for role in &data.roles {
+        sqlx::query!("INSERT IGNORE INTO category_access(category_id, role) VALUES (?, ?)", category_id, role.as_str())
+            .execute(&mut tx).await?;
     }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Set visibility of category with ID `{}` to `{}`", category_id, data.visibility.as_str())
+    ).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Deserialize)]
@@ -95,82 +144,164 @@ struct ForumCreate {
 }
 
 pub async fn forum_create(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let data: ForumCreate = req.body_json().await?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("Create forum with name `{}`", data.name)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-                let forum_id = sqlx::query!(
-                    "INSERT INTO forums(category_id, name, description) VALUES(?, ?, ?)",
-                    data.category_id, data.name, data.description
-                ).execute(&req.state().db).await?.last_insert_id();
-
-                Ok(Response::builder(StatusCode::Created).
-                    body(serde_json::to_value(forum_id)?)
-                    .build())
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            },
-            Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
-    }
+    let data: ForumCreate = req.body_json().await?;
+    let location = Location::category(data.category_id);
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Create forum with name `{}`", data.name)
+    ).execute(&req.state().db).await?;
+    let forum_id = sqlx::query!(
+        "INSERT INTO forums(category_id, name, description, position)
+         SELECT ?, ?, ?, COALESCE(MAX(position), 0) + 1 FROM forums WHERE category_id = ?",
+        data.category_id, data.name, data.description, data.category_id
+    ).execute(&req.state().db).await?.last_insert_id();
+
+    Ok(Response::builder(StatusCode::Created).
+        body(serde_json::to_value(forum_id)?)
+        .build())
 }
 
 pub async fn forum_patch(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let forum_id: u32 = req.param("forum_id")?.parse()?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("Update forum with ID `{}`", forum_id)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-                let new: BasicContainer = req.body_json().await?;
-                sqlx::query!("UPDATE forums SET name = ?, description = ? WHERE forum_id = ?",
-                    new.name, new.description, forum_id)
-                    .execute(&req.state().db).await?;
-                Ok(Response::new(StatusCode::NoContent))
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            },
-            Err(e) => Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+    let forum_id: u32 = req.param("forum_id")?.parse()?;
+    let location = permissions::forum_location(&req.state().db, forum_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let new: BasicContainer = req.body_json().await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Update forum with ID `{}`", forum_id)
+    ).execute(&req.state().db).await?;
+    sqlx::query!("UPDATE forums SET name = ?, description = ? WHERE forum_id = ?",
+        new.name, new.description, forum_id)
+        .execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[derive(Deserialize)]
+struct ForumMove {
+    category_id: u32
+}
+
+/// Moves a forum to another category. There are no stubs for forums,
+/// as every forum is listed together on the home page.
+pub async fn forum_move(mut req: Request) -> tide::Result {
+    let forum_id: u32 = req.param("forum_id")?.parse()?;
+    let from = permissions::forum_location(&req.state().db, forum_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, from).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: ForumMove = req.body_json().await?;
+    let from_category = match from.category_id {
+        Some(c) => c,
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    // a trashed category counts as missing, rather than failing the permission check below
+    if sqlx::query!("SELECT 1 AS ex FROM categories WHERE category_id = ? AND deleted IS NULL", data.category_id)
+        .fetch_optional(&req.state().db).await?.is_none() {
+        return validation::unprocessable(vec![
+            validation::Violation::new("category_id", "missing", "does not exist")
+        ])
+    }
+    let to = Location::category(data.category_id);
+    if !permissions::has(&req.state().db, Some(user_id), Permission::ManageContainers, to).await? {
+        return Ok(permissions::forbidden("missing permission `manage_containers` in the target"))
     }
+    if from_category == data.category_id {
+        return Ok(Response::new(StatusCode::NoContent))
+    }
+    let mut tx = req.state().db.begin().await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Moved forum with ID `{}` from category `{}` to category `{}`",
+            forum_id, from_category, data.category_id)
+    ).execute(&mut tx).await?;
+    // last in the new category
+    let position = sqlx::query!(
+        "SELECT COALESCE(MAX(position), 0) + 1 `position!: u32` FROM forums WHERE category_id = ?", data.category_id
+    ).fetch_one(&mut tx).await?.position;
+    sqlx::query!(
+        "UPDATE forums SET category_id = ?, position = ? WHERE forum_id = ?", data.category_id, position, forum_id
+    ).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 pub async fn forum_delete(req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let forum_id: u32 = req.param("forum_id")?.parse()?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("Delete forum with ID `{}`", forum_id)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-            sqlx::query!("DELETE FROM forums WHERE forum_id = ?", forum_id)
-                .execute(&req.state().db).await?;
-            Ok(Response::new(StatusCode::NoContent))
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            },
-            Err(e) => Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+    let forum_id: u32 = req.param("forum_id")?.parse()?;
+    let location = permissions::forum_location(&req.state().db, forum_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    if sqlx::query!(
+        "UPDATE forums SET deleted = NOW(), deleted_by = ? WHERE forum_id = ? AND deleted IS NULL",
+        user_id, forum_id
+    ).execute(&req.state().db).await?.rows_affected() == 0 {
+        return Ok(Response::new(StatusCode::NotFound))
     }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Delete forum with ID `{}`", forum_id)
+    ).execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 
+pub async fn forum_access_get(req: Request) -> tide::Result {
+    let forum_id: u32 = req.param("forum_id")?.parse()?;
+    let location = permissions::forum_location(&req.state().db, forum_id).await?;
+    if let Err(resp) = permissions::require(&req, Permission::ManageContainers, location).await? {
+        return Ok(resp)
+    }
+    let visibility = match sqlx::query!("SELECT visibility FROM forums WHERE forum_id = ?", forum_id)
+        .fetch_optional(&req.state().db).await? {
+        Some(r) => Visibility::parse(&r.visibility).unwrap_or(Visibility::Restricted),
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let roles = sqlx::query!("SELECT role FROM forum_access WHERE forum_id = ?", forum_id)
+        .fetch_all(&req.state().db).await?
+        .into_iter().filter_map(|r| Role::parse(&r.role)).collect();
+    Ok(serde_json::to_value(Access { visibility, roles })?.into())
+}
+
+pub async fn forum_access_put(mut req: Request) -> tide::Result {
+    let forum_id: u32 = req.param("forum_id")?.parse()?;
+    let location = permissions::forum_location(&req.state().db, forum_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: Access = req.body_json().await?;
+    if let Some(r) = invalid_access(&data) {
+        return Ok(r)
+    }
+    if location.forum_id.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    let mut tx = req.state().db.begin().await?;
+    sqlx::query!("UPDATE forums SET visibility = ? WHERE forum_id = ?", data.visibility.as_str(), forum_id)
+        .execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM forum_access WHERE forum_id = ?", forum_id)
+        .execute(&mut tx).await?;
+    for role in &data.roles {
+        sqlx::query!("INSERT IGNORE INTO forum_access(forum_id, role) VALUES (?, ?)", forum_id, role.as_str())
+            .execute(&mut tx).await?;
+    }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Set visibility of forum with ID `{}` to `{}`", forum_id, data.visibility.as_str())
+    ).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Deserialize)]
 struct TopicCreate {
     forum_id: u32,
@@ -179,79 +310,216 @@ struct TopicCreate {
 }
 
 pub async fn topic_create(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let data: TopicCreate = req.body_json().await?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("Create topic named `{}`", data.name)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-                let topic_id = sqlx::query!(
-                    "INSERT INTO topics(forum_id, name, description) VALUES(?, ?, ?)",
-                    data.forum_id, data.name, data.description
-                ).execute(&req.state().db).await?.last_insert_id();
-
-                Ok(Response::builder(StatusCode::Created).
-                    body(serde_json::to_value(topic_id)?)
-                    .build())
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            },
-            Err(e) => Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
-    }
+    let data: TopicCreate = req.body_json().await?;
+    let location = permissions::forum_location(&req.state().db, data.forum_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Create topic named `{}`", data.name)
+    ).execute(&req.state().db).await?;
+    let topic_id = sqlx::query!(
+        "INSERT INTO topics(forum_id, name, description, position)
+         SELECT ?, ?, ?, COALESCE(MAX(position), 0) + 1 FROM topics WHERE forum_id = ?",
+        data.forum_id, data.name, data.description, data.forum_id
+    ).execute(&req.state().db).await?.last_insert_id();
+
+    Ok(Response::builder(StatusCode::Created).
+        body(serde_json::to_value(topic_id)?)
+        .build())
 }
 
 pub async fn topic_patch(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let topic_id: u32 = req.param("topic_id")?.parse()?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("Update topic with ID `{}`", topic_id)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-                let new: BasicContainer = req.body_json().await?;
-                sqlx::query!("UPDATE topics SET name = ?, description = ? WHERE topic_id = ?",
-                new.name, new.description, topic_id)
-                    .execute(&req.state().db).await?;
-                Ok(Response::new(StatusCode::NoContent))
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            }
-            Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+    let topic_id: u32 = req.param("topic_id")?.parse()?;
+    let location = permissions::topic_location(&req.state().db, topic_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let new: BasicContainer = req.body_json().await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Update topic with ID `{}`", topic_id)
+    ).execute(&req.state().db).await?;
+    sqlx::query!("UPDATE topics SET name = ?, description = ? WHERE topic_id = ?",
+    new.name, new.description, topic_id)
+        .execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[derive(Deserialize)]
+struct TopicMove {
+    forum_id: u32,
+    // whether to leave a stub in the old forum
+    #[serde(default)]
+    redirect: bool
+}
+
+pub async fn topic_move(mut req: Request) -> tide::Result {
+    let topic_id: u32 = req.param("topic_id")?.parse()?;
+    let from = permissions::topic_location(&req.state().db, topic_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, from).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: TopicMove = req.body_json().await?;
+    let from_forum = match from.forum_id {
+        Some(f) => f,
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    // a trashed forum counts as missing, rather than failing the permission check below
+    if sqlx::query!(
+        "SELECT 1 AS ex FROM forums f INNER JOIN categories c USING (category_id)
+         WHERE forum_id = ? AND f.deleted IS NULL AND c.deleted IS NULL", data.forum_id
+    ).fetch_optional(&req.state().db).await?.is_none() {
+        return validation::unprocessable(vec![
+            validation::Violation::new("forum_id", "missing", "does not exist")
+        ])
+    }
+    let to = permissions::forum_location(&req.state().db, data.forum_id).await?;
+    if !permissions::has(&req.state().db, Some(user_id), Permission::ManageContainers, to).await? {
+        return Ok(permissions::forbidden("missing permission `manage_containers` in the target"))
+    }
+    if from_forum == data.forum_id {
+        return Ok(Response::new(StatusCode::NoContent))
+    }
+    let mut tx = req.state().db.begin().await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Moved topic with ID `{}` from forum `{}` to forum `{}`",
+            topic_id, from_forum, data.forum_id)
+    ).execute(&mut tx).await?;
+    // last in the new forum
+    let position = sqlx::query!(
+        "SELECT COALESCE(MAX(position), 0) + 1 `position!: u32` FROM topics WHERE forum_id = ?", data.forum_id
+    ).fetch_one(&mut tx).await?.position;
+    sqlx::query!(
+        "UPDATE topics SET forum_id = ?, position = ? WHERE topic_id = ?", data.forum_id, position, topic_id
+    ).execute(&mut tx).await?;
+    // moving back replaces the stub with the topic itself
+    sqlx::query!("DELETE FROM topic_stubs WHERE forum_id = ? AND topic_id = ?", data.forum_id, topic_id)
+        .execute(&mut tx).await?;
+    if data.redirect {
+        sqlx::query!(
+            "INSERT INTO topic_stubs(forum_id, topic_id) VALUES (?, ?) ON DUPLICATE KEY UPDATE created = NOW()",
+            from_forum, topic_id
+        ).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+/// Whether `ids` lists each of `children` exactly once, as a reorder must.
+fn is_permutation(ids: &[u32], mut children: Vec<u32>) -> bool {
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    children.sort_unstable();
+    ids == children
+}
+
+fn order_mismatch() -> tide::Result {
+    validation::unprocessable(vec![
+        validation::Violation::new("ids", "mismatch", "must list every child exactly once")
+    ])
+}
+
+/// Sets the display order of all categories from an ordered list of their ids.
+pub async fn category_order(mut req: Request) -> tide::Result {
+    let user_id = match permissions::guarded(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let ids: Vec<u32> = req.body_json().await?;
+    let mut tx = req.state().db.begin().await?;
+    let children = sqlx::query!("SELECT category_id FROM categories WHERE deleted IS NULL FOR UPDATE")
+        .fetch_all(&mut tx).await?.into_iter().map(|r| r.category_id).collect();
+    if !is_permutation(&ids, children) {
+        return order_mismatch()
+    }
+    sqlx::query!("INSERT INTO audit_log(user_id, log) VALUES (?, ?)", user_id, "Reordered categories")
+        .execute(&mut tx).await?;
+    for (position, category_id) in ids.iter().enumerate() {
+        sqlx::query!("UPDATE categories SET position = ? WHERE category_id = ?", position as u32 + 1, category_id)
+            .execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+/// Sets the display order of a category's forums from an ordered list of their ids.
+pub async fn forum_order(mut req: Request) -> tide::Result {
+    let category_id: u32 = req.param("category_id")?.parse()?;
+    let location = Location::category(category_id);
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let ids: Vec<u32> = req.body_json().await?;
+    let mut tx = req.state().db.begin().await?;
+    let children = sqlx::query!("SELECT forum_id FROM forums WHERE category_id = ? AND deleted IS NULL FOR UPDATE", category_id)
+        .fetch_all(&mut tx).await?.into_iter().map(|r| r.forum_id).collect();
+    if !is_permutation(&ids, children) {
+        return order_mismatch()
+    }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Reordered forums in category with ID `{}`", category_id)
+    ).execute(&mut tx).await?;
+    for (position, forum_id) in ids.iter().enumerate() {
+        sqlx::query!("UPDATE forums SET position = ? WHERE forum_id = ?", position as u32 + 1, forum_id)
+            .execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+/// Sets the display order of a forum's topics from an ordered list of their ids.
+pub async fn topic_order(mut req: Request) -> tide::Result {
+    let forum_id: u32 = req.param("forum_id")?.parse()?;
+    let location = permissions::forum_location(&req.state().db, forum_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let ids: Vec<u32> = req.body_json().await?;
+    let mut tx = req.state().db.begin().await?;
+    let children = sqlx::query!("SELECT topic_id FROM topics WHERE forum_id = ? AND deleted IS NULL FOR UPDATE", forum_id)
+        .fetch_all(&mut tx).await?.into_iter().map(|r| r.topic_id).collect();
+    if !is_permutation(&ids, children) {
+        return order_mismatch()
+    }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Reordered topics in forum with ID `{}`", forum_id)
+    ).execute(&mut tx).await?;
+    for (position, topic_id) in ids.iter().enumerate() {
+        sqlx::query!("UPDATE topics SET position = ? WHERE topic_id = ?", position as u32 + 1, topic_id)
+            .execute(&mut tx).await?;
     }
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 pub async fn topic_delete(req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let topic_id: u32 = req.param("topic_id")?.parse()?;
-        match sqlx::query!(
-            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-            user_id, format!("Delete topic with ID `{}`", topic_id)
-        ).execute(&req.state().db).await {
-            Ok(_) => {
-                sqlx::query!("DELETE FROM topics WHERE topic_id = ?", topic_id)
-                    .execute(&req.state().db).await?;
-                Ok(Response::new(StatusCode::NoContent))
-            },
-            Err(sqlx::Error::Database(e))
-            if e.code().map_or(false, |s| s == "45000") => {
-                Ok(Response::new(StatusCode::Forbidden))
-            }
-            Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
-        }
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+    let topic_id: u32 = req.param("topic_id")?.parse()?;
+    let location = permissions::topic_location(&req.state().db, topic_id).await?;
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    if sqlx::query!(
+        "UPDATE topics SET deleted = NOW(), deleted_by = ? WHERE topic_id = ? AND deleted IS NULL",
+        user_id, topic_id
+    ).execute(&req.state().db).await?.rows_affected() == 0 {
+        return Ok(Response::new(StatusCode::NotFound))
     }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Delete topic with ID `{}`", topic_id)
+    ).execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 
@@ -263,47 +531,217 @@ struct ThreadCreate {
 }
 
 pub async fn thread_create(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let data: ThreadCreate = req.body_json().await?;
-        let mut tx = req.state().db.begin().await?;
-        let t_result = sqlx::query!(
-            "INSERT INTO threads(name, topic_id) VALUES(?, ?)",
-            data.name, data.topic_id
-        ).execute(&mut tx).await?;
-        let thread_id = t_result.last_insert_id() as u32;
-        sqlx::query!(
-            "INSERT INTO posts(thread_id, user_id, content) VALUES (?, ?, ?)",
-            thread_id, user_id, data.post_content
-        ).execute(&mut tx).await?;
-        tx.commit().await?;
-        Ok(Response::builder(StatusCode::Created)
-            .header(
-                "Location",
-                req.url().join(&thread_id.to_string())?.as_str()
-            ).body(serde_json::to_value(thread_id)?).build())
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+    let data: ThreadCreate = req.body_json().await?;
+    let location = permissions::topic_location(&req.state().db, data.topic_id).await?;
+    let user_id = match permissions::require(&req, Permission::CreateThread, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    // missing or deleted
+    if location.forum_id.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    if let Some(ban) = bans::active(&req.state().db, user_id, location).await? {
+        return Ok(bans::banned(&ban))
     }
+    if !email::may_post(&req.state().db, user_id, true).await? {
+        return Ok(permissions::forbidden("email verification required"))
+    }
+    let mut tx = req.state().db.begin().await?;
+    let t_result = sqlx::query!(
+        "INSERT INTO threads(name, topic_id) VALUES(?, ?)",
+        data.name, data.topic_id
+    ).execute(&mut tx).await?;
+    let thread_id = t_result.last_insert_id() as u32;
+    sqlx::query!(
+        "INSERT INTO posts(thread_id, user_id, content) VALUES (?, ?, ?)",
+        thread_id, user_id, data.post_content
+    ).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Response::builder(StatusCode::Created)
+        .header(
+            "Location",
+            req.url().join(&thread_id.to_string())?.as_str()
+        ).body(serde_json::to_value(thread_id)?).build())
 }
 
 pub async fn thread_delete(req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let thread_id: u32 = req.param("thread_id")?.parse()?;
-        let allowed = sqlx::query!(
-            "SELECT (user_id = ? OR (SELECT is_admin FROM users u WHERE u.user_id = p.user_id)) `ok!: bool`
-             FROM posts p WHERE post_pos = 1 AND thread_id = ?",
-            user_id, thread_id
-        ).fetch_one(&req.state().db).await?;
-        if allowed.ok {
-            sqlx::query!("DELETE FROM threads WHERE thread_id = ?", thread_id)
-                .execute(&req.state().db).await?;
-            Ok(Response::new(StatusCode::NoContent))
-        } else {
-            Ok(Response::new(StatusCode::Forbidden))
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let thread_id: u32 = req.param("thread_id")?.parse()?;
+    let location = permissions::thread_location(&req.state().db, thread_id).await?;
+    if location.forum_id.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    let author = sqlx::query!(
+        "SELECT user_id = ? `author!: bool` FROM posts WHERE post_pos = 1 AND thread_id = ?",
+        user_id, thread_id
+    ).fetch_one(&req.state().db).await?.author;
+    if author || permissions::has(&req.state().db, Some(user_id), Permission::ModeratePosts, location).await? {
+        // restorable from the trash until purged
+        sqlx::query!(
+            "UPDATE threads SET deleted = NOW(), deleted_by = ? WHERE thread_id = ?", user_id, thread_id
+        ).execute(&req.state().db).await?;
+        if !author {
+            sqlx::query!(
+                "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+                user_id, format!("Deleted thread (ID: {})", thread_id)
+            ).execute(&req.state().db).await?;
         }
+        Ok(Response::new(StatusCode::NoContent))
     } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+        Ok(permissions::forbidden("only the author or a moderator can delete this"))
+    }
+}
+
+#[derive(Deserialize)]
+struct ThreadPatch {
+    name: Option<String>,
+    // the content of the opening post, which is edited like any post
+    description: Option<String>
+}
+
+pub async fn thread_patch(mut req: Request) -> tide::Result {
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let thread_id: u32 = req.param("thread_id")?.parse()?;
+    let data: ThreadPatch = req.body_json().await?;
+    if data.name.as_deref().map_or(false, |n| n.trim().is_empty()) {
+        return validation::unprocessable(vec![
+            validation::Violation::new("name", "empty", "must not be empty")
+        ])
+    }
+    let opening = match sqlx::query!(
+        "SELECT post_id, user_id = ? `author!: bool`, archived `archived: bool`, locked `locked: bool`
+         FROM posts INNER JOIN threads th USING (thread_id)
+         WHERE post_pos = 1 AND thread_id = ? AND th.deleted IS NULL",
+        user_id, thread_id
+    ).fetch_optional(&req.state().db).await? {
+        Some(p) => p,
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let location = permissions::thread_location(&req.state().db, thread_id).await?;
+    if !opening.author
+        && !permissions::has(&req.state().db, Some(user_id), Permission::ModeratePosts, location).await? {
+        return Ok(permissions::forbidden("only the author or a moderator can edit this"))
+    }
+    if let Some(ban) = bans::active(&req.state().db, user_id, location).await? {
+        return Ok(bans::banned(&ban))
+    }
+    if opening.archived {
+        return Ok(permissions::forbidden("thread is archived"))
+    }
+    if opening.locked && !permissions::has(&req.state().db, Some(user_id), Permission::LockThreads, location).await? {
+        return Ok(permissions::forbidden("thread is locked"))
+    }
+    let mut tx = req.state().db.begin().await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Update thread with ID `{}`", thread_id)
+    ).execute(&mut tx).await?;
+    sqlx::query!("UPDATE threads SET name = COALESCE(?, name) WHERE thread_id = ?", data.name, thread_id)
+        .execute(&mut tx).await?;
+    if let Some(description) = data.description {
+        revise_post(&mut tx, opening.post_id, user_id, &description).await?;
+    }
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[derive(Deserialize)]
+struct ThreadMove {
+    topic_id: u32,
+    // whether to leave a stub in the old topic
+    #[serde(default)]
+    redirect: bool
+}
+
+pub async fn thread_move(mut req: Request) -> tide::Result {
+    let thread_id: u32 = req.param("thread_id")?.parse()?;
+    let from = permissions::thread_location(&req.state().db, thread_id).await?;
+    let user_id = match permissions::require(&req, Permission::MoveThreads, from).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: ThreadMove = req.body_json().await?;
+    let from_topic = match sqlx::query!(
+        "SELECT topic_id FROM threads WHERE thread_id = ? AND deleted IS NULL", thread_id
+    ).fetch_optional(&req.state().db).await? {
+        Some(r) => r.topic_id,
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let to = permissions::topic_location(&req.state().db, data.topic_id).await?;
+    if to.forum_id.is_none() {
+        return validation::unprocessable(vec![
+            validation::Violation::new("topic_id", "missing", "does not exist")
+        ])
+    }
+    if !permissions::has(&req.state().db, Some(user_id), Permission::MoveThreads, to).await? {
+        return Ok(permissions::forbidden("missing permission `move_threads` in the target"))
+    }
+    if from_topic == data.topic_id {
+        return Ok(Response::new(StatusCode::NoContent))
+    }
+    let mut tx = req.state().db.begin().await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Moved thread with ID `{}` from topic `{}` to topic `{}`",
+            thread_id, from_topic, data.topic_id)
+    ).execute(&mut tx).await?;
+    sqlx::query!("UPDATE threads SET topic_id = ? WHERE thread_id = ?", data.topic_id, thread_id)
+        .execute(&mut tx).await?;
+    // moving back replaces the stub with the thread itself
+    sqlx::query!("DELETE FROM thread_stubs WHERE topic_id = ? AND thread_id = ?", data.topic_id, thread_id)
+        .execute(&mut tx).await?;
+    if data.redirect {
+        sqlx::query!(
+            "INSERT INTO thread_stubs(topic_id, thread_id) VALUES (?, ?) ON DUPLICATE KEY UPDATE created = NOW()",
+            from_topic, thread_id
+        ).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[derive(Deserialize)]
+struct ThreadState {
+    locked: Option<bool>,
+    pinned: Option<bool>,
+    archived: Option<bool>
+}
+
+/// Locks, pins or archives a thread, leaving out fields unchanged.
+pub async fn thread_state(mut req: Request) -> tide::Result {
+    let thread_id: u32 = req.param("thread_id")?.parse()?;
+    let location = permissions::thread_location(&req.state().db, thread_id).await?;
+    let user_id = match permissions::require(&req, Permission::LockThreads, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: ThreadState = req.body_json().await?;
+    if location.forum_id.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    let mut tx = req.state().db.begin().await?;
+    for (state, value) in [("locked", data.locked), ("pinned", data.pinned), ("archived", data.archived)] {
+        if let Some(v) = value {
+            sqlx::query!(
+                "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+                user_id, format!("Set {} to {} for thread with ID `{}`", state, v, thread_id)
+            ).execute(&mut tx).await?;
+        }
     }
+    sqlx::query!(
+        "UPDATE threads SET locked = COALESCE(?, locked), pinned = COALESCE(?, pinned),
+         archived = COALESCE(?, archived) WHERE thread_id = ?",
+        data.locked, data.pinned, data.archived, thread_id
+    ).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 #[derive(Deserialize)]
@@ -319,82 +757,178 @@ struct PostCreated {
 }
 
 pub async fn post_create(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let data: PostCreate = req.body_json().await?;
-        let r = sqlx::query!(
-            "CALL insert_post(?, ?, ?)",
-            data.thread_id, user_id, data.content
-        ).fetch_one(&req.state().db).await?;
-        tide::log::debug!("post create record: {:?}", r);
-        let resp = Response::builder(StatusCode::Created)
-            .body(serde_json::to_value(PostCreated {
-                post_id: r.get_unchecked(0),
-                page_num: (r.get_unchecked::<u32, _>(1) - 1) / PAGE_SIZE as u32 + 1
-            })?)
-            .build();
-        tide::log::debug!("post create resp: {:?}", resp);
-        Ok(resp)
-    } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+    let data: PostCreate = req.body_json().await?;
+    let location = permissions::thread_location(&req.state().db, data.thread_id).await?;
+    let user_id = match permissions::require(&req, Permission::CreatePost, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    // missing or deleted
+    if location.forum_id.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    if let Some(ban) = bans::active(&req.state().db, user_id, location).await? {
+        return Ok(bans::banned(&ban))
+    }
+    if !email::may_post(&req.state().db, user_id, false).await? {
+        return Ok(permissions::forbidden("email verification required"))
+    }
+    // the thread row stays locked until the post is in, so locking or archiving cannot slip in between
+    let mut tx = req.state().db.begin().await?;
+    if let Some(t) = sqlx::query!(
+        "SELECT locked `locked: bool`, archived `archived: bool` FROM threads WHERE thread_id = ? FOR UPDATE",
+        data.thread_id
+    ).fetch_optional(&mut tx).await? {
+        // moderators can still reply to locked threads, nobody can to archived ones
+        if t.archived {
+            return Ok(permissions::forbidden("thread is archived"))
+        }
+        if t.locked && !permissions::has(&req.state().db, Some(user_id), Permission::LockThreads, location).await? {
+            return Ok(permissions::forbidden("thread is locked"))
+        }
+    }
+    let r = sqlx::query!(
+        "CALL insert_post(?, ?, ?)",
+        data.thread_id, user_id, data.content
+    ).fetch_one(&mut tx).await?;
+    tx.commit().await?;
+    tide::log::debug!("post create record: {:?}", r);
+    let resp = Response::builder(StatusCode::Created)
+        .body(serde_json::to_value(PostCreated {
+            post_id: r.get_unchecked(0),
+            page_num: (r.get_unchecked::<u32, _>(1) - 1) / PAGE_SIZE as u32 + 1
+        })?)
+        .build();
+    tide::log::debug!("post create resp: {:?}", resp);
+    Ok(resp)
+}
+
+/// Replaces a post's content, keeping the previous version as a revision.
+/// Returns whether the content changed.
+async fn revise_post(tx: &mut Transaction<'_, MySql>, post_id: u32, editor_id: u32, content: &str)
+    -> sqlx::Result<bool> {
+    let current = sqlx::query!("SELECT content FROM posts WHERE post_id = ? FOR UPDATE", post_id)
+        .fetch_one(&mut *tx).await?.content;
+    if current == content {
+        return Ok(false)
     }
+    sqlx::query!(
+        "INSERT INTO post_revisions(post_id, editor_id, content) VALUES (?, ?, ?)",
+        post_id, editor_id, current
+    ).execute(&mut *tx).await?;
+    sqlx::query!("UPDATE posts SET content = ?, edited = NOW() WHERE post_id = ?", content, post_id)
+        .execute(&mut *tx).await?;
+    Ok(true)
+}
+
+#[derive(Deserialize)]
+struct PostEdit {
+    content: String
+}
+
+/// Replaces a post's content, keeping the previous version as a revision.
+pub async fn post_edit(mut req: Request) -> tide::Result {
+    let post_id: u32 = req.param("post_id")?.parse()?;
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: PostEdit = req.body_json().await?;
+    let location = permissions::post_location(&req.state().db, post_id).await?;
+    let post = match sqlx::query!(
+        "SELECT user_id, locked `locked: bool`, archived `archived: bool`
+         FROM posts p INNER JOIN threads th USING (thread_id)
+         WHERE post_id = ? AND p.deleted IS NULL AND th.deleted IS NULL", post_id
+    ).fetch_optional(&req.state().db).await? {
+        Some(p) => p,
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let author = post.user_id == user_id;
+    let moderator = permissions::has(&req.state().db, Some(user_id), Permission::ModeratePosts, location).await?;
+    if !author && !moderator {
+        return Ok(permissions::forbidden("only the author or a moderator can edit this"))
+    }
+    if let Some(ban) = bans::active(&req.state().db, user_id, location).await? {
+        return Ok(bans::banned(&ban))
+    }
+    if post.archived {
+        return Ok(permissions::forbidden("thread is archived"))
+    }
+    if post.locked && !moderator {
+        return Ok(permissions::forbidden("thread is locked"))
+    }
+
+    let mut tx = req.state().db.begin().await?;
+    if !revise_post(&mut tx, post_id, user_id, &data.content).await? {
+        return Ok(Response::new(StatusCode::NoContent))
+    }
+    if !author {
+        sqlx::query!(
+            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+            user_id, format!("Edited post (Post ID: {})", post_id)
+        ).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 pub async fn post_delete(req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let post_id: u32 = req.param("post_id")?.parse()?;
-        let perm_check = sqlx::query!(
-            "SELECT (
-               EXISTS(SELECT * FROM posts p WHERE p.user_id = u.user_id AND post_id = ?)
-            ) `poster: bool`, is_admin `admin: bool`
-            FROM users u WHERE u.user_id = ?",
-            post_id, user_id
-        ).fetch_one(&req.state().db).await?;
-        if perm_check.poster || perm_check.admin {
-            let mut tx = req.state().db.begin().await?;
-            // trigger dont work because mysql cannot update current table
-            // procedure dont work because variables must be scalars and i cannot split a query with 2 cols
-            let info = sqlx::query!(
-                "SELECT thread_id, post_pos, last_pos FROM posts INNER JOIN threads USING (thread_id)\
-                 WHERE post_id = ?", post_id
-            ).fetch_one(&mut tx).await?;
-            tide::log::debug!("DELETE POST: {}, {}", info.thread_id, info.post_pos);
-            if info.post_pos == 1 {
-                sqlx::query!("DELETE FROM threads WHERE thread_id = ?", info.thread_id)
-                    .execute(&mut tx).await?;
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let post_id: u32 = req.param("post_id")?.parse()?;
+    let location = permissions::post_location(&req.state().db, post_id).await?;
+    if location.forum_id.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    let info = sqlx::query!(
+        "SELECT thread_id, post_pos, user_id = ? `poster!: bool` FROM posts WHERE post_id = ?", user_id, post_id
+    ).fetch_one(&req.state().db).await?;
+    if info.poster || permissions::has(&req.state().db, Some(user_id), Permission::ModeratePosts, location).await? {
+        let mut tx = req.state().db.begin().await?;
+        // positions stay as they are until purged, so restoring puts the post back in place
+        if info.post_pos == 1 {
+            sqlx::query!(
+                "UPDATE threads SET deleted = NOW(), deleted_by = ? WHERE thread_id = ?", user_id, info.thread_id
+            ).execute(&mut tx).await?;
+            sqlx::query!(
+                "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+                user_id, format!("Deleted thread (ID: {})", info.thread_id)
+            ).execute(&mut tx).await?;
+        } else {
+            sqlx::query!("UPDATE posts SET deleted = NOW(), deleted_by = ? WHERE post_id = ?", user_id, post_id)
+                .execute(&mut tx).await?;
+            if !info.poster {
                 sqlx::query!(
                     "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-                    user_id, format!("Deleted thread (ID: {})", info.thread_id)
-                ).execute(&mut tx).await?;
-            } else {
-                // i do not like mysql.
-                tx.execute("DROP TRIGGER IF EXISTS post_delete_up_last_pos");
-                sqlx::query!("UPDATE threads SET last_pos = NULL WHERE thread_id = ?", info.thread_id)
-                    .execute(&mut tx).await?;
-                sqlx::query!("DELETE FROM posts WHERE post_id = ?", post_id)
-                    .execute(&mut tx).await?;
-                tide::log::debug!("DELETE POST: {:?}",
-                    sqlx::query!("SELECT EXISTS(SELECT * FROM threads WHERE thread_id = ?) `a: bool`",
-                        info.thread_id).fetch_one(&mut tx).await?.a);
-                sqlx::query!(
-                    "UPDATE posts SET post_pos = post_pos - 1 WHERE thread_id = ? AND post_pos > ?",
-                    info.thread_id, info.post_pos
+                    user_id, format!("Deleted post (Post ID: {}, Thread ID: {})", post_id, info.thread_id)
                 ).execute(&mut tx).await?;
-                sqlx::query!("UPDATE threads SET last_pos = ? - 1 WHERE thread_id = ?", info.last_pos, info.thread_id)
-                    .execute(&mut tx).await?;
-                if !perm_check.poster {
-                    sqlx::query!(
-                        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
-                        user_id, format!("Deleted post (Post ID: {}, Thread ID: {})", post_id, info.thread_id)
-                    ).execute(&mut tx).await?;
-                }
             }
-
-            tx.commit().await?;
-            Ok(Response::new(StatusCode::ResetContent))
-        } else {
-            Ok(Response::new(StatusCode::Forbidden))
         }
+
+        tx.commit().await?;
+        Ok(Response::new(StatusCode::ResetContent))
     } else {
-        Ok(Response::new(StatusCode::Unauthorized))
+        Ok(permissions::forbidden("only the author or a moderator can delete this"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_permutation_takes_any_order_of_every_child() {
+        assert!(is_permutation(&[3, 1, 2], vec![1, 2, 3]));
+        assert!(is_permutation(&[], vec![]));
+    }
+
+    #[test]
+    fn is_permutation_refuses_missing_extra_or_repeated_ids() {
+        assert!(!is_permutation(&[1, 2], vec![1, 2, 3]));
+        assert!(!is_permutation(&[1, 2, 3, 4], vec![1, 2, 3]));
+        assert!(!is_permutation(&[1, 1, 2], vec![1, 2, 3]));
+        assert!(!is_permutation(&[1, 2, 4], vec![1, 2, 3]));
     }
 }
@@ -0,0 +1,58 @@
+use tide::{Body, Response, StatusCode};
+
+use crate::{Request, utils::{export, permissions}, models::DataExport};
+
+pub async fn export_list(req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data = sqlx::query_as!(DataExport,
+        "SELECT export_id, status, created, expiry FROM data_exports
+         WHERE user_id = ? AND (expiry IS NULL OR expiry > NOW()) ORDER BY export_id DESC",
+        user_id
+    ).fetch_all(&req.state().db).await?;
+    Ok(serde_json::to_value(data)?.into())
+}
+
+pub async fn export_create(req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    // one pending too long was lost to a restart, the sweep marks it failed
+    if sqlx::query!(
+        "SELECT 1 AS ex FROM data_exports
+         WHERE user_id = ? AND status = 'pending' AND created > NOW() - INTERVAL ? MINUTE",
+        user_id, *export::PENDING_MINUTES
+    ).fetch_optional(&req.state().db).await?.is_some() {
+        return Ok(Response::builder(StatusCode::Conflict).body("an export is already being made").build())
+    }
+    let export_id = sqlx::query!(
+        "INSERT INTO data_exports(user_id, status) VALUES (?, 'pending')", user_id
+    ).execute(&req.state().db).await?.last_insert_id() as u32;
+    async_std::task::spawn(export::run(req.state().db.clone(), export_id, user_id));
+    Ok(Response::builder(StatusCode::Accepted)
+        .body(serde_json::to_value(export_id)?)
+        .build())
+}
+
+pub async fn export_download(req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let export_id = req.param("export_id")?.parse::<u32>()?;
+    if sqlx::query!(
+        "SELECT 1 AS ex FROM data_exports
+         WHERE export_id = ? AND user_id = ? AND status = 'ready' AND expiry > NOW()",
+        export_id, user_id
+    ).fetch_optional(&req.state().db).await?.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    Ok(Response::builder(StatusCode::Ok)
+        .body(Body::from_file(export::export_path(export_id)).await?)
+        .content_type("application/json")
+        .header("Content-Disposition", format!("attachment; filename=\"export-{}.json\"", export_id))
+        .build())
+}
@@ -2,7 +2,7 @@ use async_std::{path::{Path, PathBuf}, fs};
 use lazy_static::lazy_static;
 use serde::{Serialize, Deserialize};
 use tide::StatusCode;
-use crate::Request;
+use crate::{Request, utils::{bans, permissions::{self, Location}}};
 
 #[derive(Serialize, Deserialize)]
 struct Image {
@@ -15,22 +15,41 @@ lazy_static! {
     static ref AVATAR_DIR: PathBuf = IMAGE_DIR.join("avatars");
 }
 
-pub(crate) async fn set_avatar(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let file_type = req.content_type()
-            .ok_or(tide::Error::from_str(StatusCode::BadRequest, "Content-Type Invalid"))?;
-        if !matches!(file_type.subtype(), "jpeg" | "png") {
-            return Err(tide::Error::from_str(StatusCode::BadRequest, "Content-Type Not Accepted"));
-        }
-        fs::write(
-            AVATAR_DIR.join(format!("{}", user_id)),
-            req.body_bytes().await?
-        ).await?;
-        sqlx::query!("UPDATE users SET is_avatar_set = TRUE WHERE user_id = ?", user_id)
-            .execute(&req.state().db).await?;
+pub(crate) async fn read_avatar(user_id: u32) -> std::io::Result<Option<Vec<u8>>> {
+    match fs::read(AVATAR_DIR.join(format!("{}", user_id))).await {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+    }
+}
 
-        Ok(StatusCode::NoContent.into())
-    } else {
-        Ok(StatusCode::Unauthorized.into())
+/// Deletes a user's avatar file. Not having one is fine.
+pub(crate) async fn remove_avatar(user_id: u32) -> std::io::Result<()> {
+    match fs::remove_file(AVATAR_DIR.join(format!("{}", user_id))).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(())
     }
 }
+
+pub(crate) async fn set_avatar(mut req: Request) -> tide::Result {
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    if let Some(ban) = bans::active(&req.state().db, user_id, Location::GLOBAL).await? {
+        return Ok(bans::banned(&ban))
+    }
+    let file_type = req.content_type()
+        .ok_or(tide::Error::from_str(StatusCode::BadRequest, "Content-Type Invalid"))?;
+    if !matches!(file_type.subtype(), "jpeg" | "png") {
+        return Err(tide::Error::from_str(StatusCode::BadRequest, "Content-Type Not Accepted"));
+    }
+    fs::write(
+        AVATAR_DIR.join(format!("{}", user_id)),
+        req.body_bytes().await?
+    ).await?;
+    sqlx::query!("UPDATE users SET is_avatar_set = TRUE WHERE user_id = ?", user_id)
+        .execute(&req.state().db).await?;
+
+    Ok(StatusCode::NoContent.into())
+}
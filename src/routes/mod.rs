@@ -2,15 +2,23 @@ use tide::{Response, Server, StatusCode};
 use tide::sessions;
 use tide::http::cookies::SameSite;
 
-use crate::{Request, State, utils};
+use crate::{Request, State, utils, middleware, models::Permission};
 
 mod users;
 mod containers;
 mod containers_modify;
-mod images;
+pub(crate) mod images;
 mod auth;
 mod search;
 mod reactions;
+mod totp;
+mod tokens;
+mod user_sessions;
+mod export;
+mod oidc;
+mod roles;
+mod bans;
+mod trash;
 
 async fn ok(_: Request) -> tide::Result {
     Ok(Response::new(StatusCode::NoContent))
@@ -27,7 +35,14 @@ pub fn add_routes(app: &mut Server<State>, image_file_dir: &str) {
             std::env::var("SESSION_SECRET")
                 .expect("SESSION_SECRET env var should be set")
         ).expect("SESSION_SECRET should contain valid hex").as_slice()
-    ).with_same_site_policy(SameSite::Lax).with_cookie_name("10_c"));
+    ).with_same_site_policy(SameSite::Lax)
+        .with_cookie_name(utils::sessions::COOKIE_NAME)
+        // the default of a day would cut remembered sessions short, `SessionTrackMiddleware` shortens it
+        .with_session_ttl(Some(*utils::sessions::LONG_LIFETIME)));
+    api.with(middleware::SessionTrackMiddleware);
+    api.with(middleware::CsrfMiddleware);
+    api.with(middleware::BearerAuthMiddleware);
+    api.with(middleware::CurrentUserMiddleware);
 
     let mut images = api.at("/images");
     images.at("/set_avatar").post(images::set_avatar);
@@ -36,10 +51,23 @@ pub fn add_routes(app: &mut Server<State>, image_file_dir: &str) {
     api.at("/latest").get(containers::latest_posts);
 
     let mut categories = api.at("/categories");
-    categories.get(containers::all_categories).post(containers_modify::category_create);
+    categories.get(containers::all_categories);
+    categories.at("/")
+        .with(middleware::RequirePermission(Permission::ManageContainers))
+        .post(containers_modify::category_create);
+    categories.at("/order")
+        .with(middleware::RequirePermission(Permission::ManageContainers))
+        .put(containers_modify::category_order);
     categories.at("/:category_id")
         .patch(containers_modify::category_patch)
         .delete(containers_modify::category_delete);
+    categories.at("/:category_id/order").put(containers_modify::forum_order);
+    categories.at("/:category_id/restore")
+        .with(middleware::RequirePermission(Permission::ManageContainers))
+        .post(trash::category_restore);
+    categories.at("/:category_id/access")
+        .get(containers_modify::category_access_get)
+        .put(containers_modify::category_access_put);
 
     let mut forums = api.at("/forums");
     forums.get(containers::all_forums).post(containers_modify::forum_create);
@@ -47,6 +75,12 @@ pub fn add_routes(app: &mut Server<State>, image_file_dir: &str) {
         .get(containers::forum_data)
         .patch(containers_modify::forum_patch)
         .delete(containers_modify::forum_delete);
+    forums.at("/:forum_id/move").post(containers_modify::forum_move);
+    forums.at("/:forum_id/order").put(containers_modify::topic_order);
+    forums.at("/:forum_id/restore").post(trash::forum_restore);
+    forums.at("/:forum_id/access")
+        .get(containers_modify::forum_access_get)
+        .put(containers_modify::forum_access_put);
 
     let mut topics = api.at("/topics");
     topics.get(containers::all_topics).post(containers_modify::topic_create);
@@ -56,37 +90,87 @@ pub fn add_routes(app: &mut Server<State>, image_file_dir: &str) {
             .patch(containers_modify::topic_patch)
             .delete(containers_modify::topic_delete)
         .at("/page/:page_num").get(containers::topic_pages);
+    topics.at("/:topic_id/move").post(containers_modify::topic_move);
+    topics.at("/:topic_id/restore").post(trash::topic_restore);
 
     let mut threads = api.at("/threads");
     threads.post(containers_modify::thread_create);
     threads
         .at("/:thread_id")
             .get(containers::thread_info)
+            .patch(containers_modify::thread_patch)
             .delete(containers_modify::thread_delete)
-        .at("/page/:page_num").get(containers::thread_pages);
+        .at("/state").put(containers_modify::thread_state);
+    threads.at("/:thread_id/page/:page_num").get(containers::thread_pages);
+    threads.at("/:thread_id/move").post(containers_modify::thread_move);
+    threads.at("/:thread_id/restore").post(trash::thread_restore);
 
     let mut posts = api.at("/posts");
     posts.post(containers_modify::post_create);
     let mut post_specific = posts.at("/:post_id");
-    post_specific.delete(containers_modify::post_delete);
+    post_specific.patch(containers_modify::post_edit).delete(containers_modify::post_delete);
+    post_specific.at("/revisions").get(containers::revision_list);
+    post_specific.at("/restore").post(trash::post_restore);
+    post_specific.at("/revisions/:revision_id/diff").get(containers::revision_diff);
     post_specific.at("/reactions/add").post(reactions::add_reaction);
     post_specific.at("/reactions/rem").post(reactions::rem_reaction);
 
     api.at("/reactions").get(reactions::all_reactions);
 
+    api.at("/trash").get(trash::trash_list);
+
     let mut users = api.at("/users");
     users.at("/available").get(users::available_username);
     let mut user_specific = users.at("/:user_id");
     user_specific
         .get(users::user_get)
-        .patch(users::user_patch);
+        .patch(users::user_patch)
+        .delete(users::user_delete);
     user_specific.at("/logs").get(users::log_get);
+    user_specific.at("/require_totp")
+        .with(middleware::RequirePermission(Permission::ManageUsers))
+        .post(users::require_totp);
+    user_specific.at("/tokens").get(tokens::token_list).post(tokens::token_create);
+    user_specific.at("/tokens/:token_id").delete(tokens::token_revoke);
+    user_specific.at("/sessions")
+        .get(user_sessions::session_list)
+        .delete(user_sessions::session_revoke_all);
+    user_specific.at("/sessions/:session_no").delete(user_sessions::session_revoke);
+    user_specific.at("/export").get(export::export_list).post(export::export_create);
+    user_specific.at("/export/:export_id").get(export::export_download);
+    user_specific.at("/identities").get(oidc::identity_list).post(oidc::identity_link);
+    user_specific.at("/identities/:identity_id").delete(oidc::identity_unlink);
+    user_specific.at("/roles").get(roles::user_roles);
+    user_specific.at("/roles")
+        .with(middleware::RequirePermission(Permission::ManageRoles))
+        .post(roles::role_grant);
+    user_specific.at("/roles/:role")
+        .with(middleware::RequirePermission(Permission::ManageRoles))
+        .delete(roles::role_revoke);
+    user_specific.at("/bans").post(bans::ban_create);
+    user_specific.at("/bans/:ban_id").delete(bans::ban_lift);
+
+    api.at("/logs/security")
+        .with(middleware::RequirePermission(Permission::ViewLogs))
+        .get(users::security_log_get);
+    api.at("/roles").get(roles::role_list);
 
     let mut auth = api.at("/auth");
+    auth.at("/csrf").get(middleware::csrf_token);
     auth.at("/pre_auth").post(auth::pre_auth);
     auth.at("/register").post(auth::register);
     auth.at("/login").post(auth::login);
+    auth.at("/login/totp").post(totp::login_totp);
     auth.at("/logout").post(auth::logout);
+    auth.at("/password").post(auth::change_password);
+    auth.at("/reset").post(auth::reset_request);
+    auth.at("/reset/confirm").post(auth::reset_confirm);
+    auth.at("/totp").post(totp::enrol_start).delete(totp::disable);
+    auth.at("/totp/confirm").post(totp::enrol_confirm);
+    auth.at("/verify_email").post(auth::verify_email);
+    auth.at("/verify_email/resend").post(auth::resend_verification);
+    auth.at("/oidc/login").post(oidc::login_start);
+    auth.at("/oidc/callback").post(oidc::callback);
 
     let mut search = api.at("/search");
     search.at("/users").get(search::user_search);
@@ -0,0 +1,155 @@
+use tide::{Response, StatusCode};
+use serde::{Serialize, Deserialize};
+
+use crate::{Request, utils::{bans, oidc, permissions}, models::LinkedIdentity};
+use crate::routes::auth::finish_login;
+
+const PENDING_KEY: &str = "oidc_pending";
+
+#[derive(Deserialize)]
+struct Start {
+    #[serde(default)]
+    remember: bool
+}
+
+#[derive(Serialize)]
+struct Redirect {
+    // where to send the user, the provider sends them back to `OIDC_REDIRECT_URI`
+    url: String
+}
+
+#[derive(Deserialize)]
+struct Callback {
+    code: String,
+    state: String
+}
+
+fn not_configured() -> Response {
+    Response::builder(StatusCode::NotFound).body("single sign-on is not configured").build()
+}
+
+async fn start(mut req: Request, link_user: Option<u32>, remember: bool) -> tide::Result {
+    let config = match oidc::CONFIG.as_ref() {
+        Some(c) => c,
+        None => return Ok(not_configured())
+    };
+    let (url, pending) = oidc::begin(config, link_user, remember).await?;
+    req.session_mut().insert(PENDING_KEY, pending)?;
+    Ok(serde_json::to_value(Redirect { url })?.into())
+}
+
+pub async fn login_start(mut req: Request) -> tide::Result {
+    let data: Start = req.body_json().await?;
+    start(req, None, data.remember).await
+}
+
+/// Finishes a login or link, with the `code` and `state` the provider sent back.
+pub async fn callback(mut req: Request) -> tide::Result {
+    let config = match oidc::CONFIG.as_ref() {
+        Some(c) => c,
+        None => return Ok(not_configured())
+    };
+    let data: Callback = req.body_json().await?;
+    let pending = match req.session().get::<oidc::Pending>(PENDING_KEY) {
+        Some(p) => p,
+        None => return Ok(Response::builder(StatusCode::Forbidden)
+            .body("no single sign-on login in progress").build())
+    };
+    // one go per start, a failed callback needs starting over
+    req.session_mut().remove(PENDING_KEY);
+    if pending.expired() || pending.state != data.state {
+        return Ok(Response::builder(StatusCode::Forbidden).body("invalid or expired login state").build())
+    }
+    let claims = match oidc::finish(config, &pending, &data.code).await? {
+        Some(c) => c,
+        None => return Ok(Response::builder(StatusCode::Forbidden)
+            .body("identity provider login could not be verified").build())
+    };
+
+    if let Some(user_id) = pending.link_user {
+        // the session could have been logged out or into someone else meanwhile
+        if permissions::user_id(&req) != Some(user_id) {
+            return Ok(permissions::unauthorized())
+        }
+        let identity_id = match sqlx::query!(
+            "INSERT INTO user_identities(user_id, issuer, subject, email) VALUES (?, ?, ?, ?)",
+            user_id, config.issuer, claims.sub, claims.email
+        ).execute(&req.state().db).await {
+            Ok(r) => r.last_insert_id(),
+            Err(sqlx::Error::Database(e))
+            if e.code().map_or(false, |s| s == "23000") => return Ok(Response::builder(StatusCode::Conflict)
+                .body("this identity is already linked to an account").build()),
+            Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
+        };
+        sqlx::query!(
+            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+            user_id, format!("Linked identity `{}` from `{}`", claims.sub, config.issuer)
+        ).execute(&req.state().db).await?;
+        return Ok(Response::builder(StatusCode::Created)
+            .body(serde_json::to_value(identity_id)?)
+            .build())
+    }
+
+    // accounts are never made here, an identity has to be linked from an existing one first
+    let data = match sqlx::query!(
+        "SELECT user_id, is_admin `is_admin: bool`,
+         totp_enabled `totp_enabled: bool`, totp_required `totp_required: bool`
+         FROM user_identities INNER JOIN users USING (user_id) WHERE issuer = ? AND subject = ?",
+        config.issuer, claims.sub
+    ).fetch_optional(&req.state().db).await? {
+        Some(d) => d,
+        None => return Ok(Response::builder(StatusCode::Forbidden)
+            .body("no account is linked to this identity").build())
+    };
+    if let Some(ban) = bans::permanent(&req.state().db, data.user_id).await? {
+        return Ok(bans::banned(&ban))
+    }
+    finish_login(
+        req.session_mut(), data.user_id, data.is_admin, data.totp_enabled, data.totp_required, pending.remember
+    )
+}
+
+pub async fn identity_list(req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data = sqlx::query_as!(LinkedIdentity,
+        "SELECT identity_id, issuer, subject, email, created
+         FROM user_identities WHERE user_id = ? ORDER BY identity_id",
+        user_id
+    ).fetch_all(&req.state().db).await?;
+    Ok(serde_json::to_value(data)?.into())
+}
+
+/// Starts linking a provider identity, finished by `callback`.
+pub async fn identity_link(req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    start(req, Some(user_id), false).await
+}
+
+/// Every account has a password, so unlinking never leaves one without a way in.
+pub async fn identity_unlink(req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let identity_id = req.param("identity_id")?.parse::<u32>()?;
+    let identity = match sqlx::query!(
+        "SELECT issuer, subject FROM user_identities WHERE identity_id = ? AND user_id = ?",
+        identity_id, user_id
+    ).fetch_optional(&req.state().db).await? {
+        Some(i) => i,
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    sqlx::query!("DELETE FROM user_identities WHERE identity_id = ?", identity_id)
+        .execute(&req.state().db).await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Unlinked identity `{}` from `{}`", identity.subject, identity.issuer)
+    ).execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
@@ -1,5 +1,5 @@
 use tide::StatusCode;
-use crate::Request;
+use crate::{Request, models::Permission, utils::{bans, permissions}};
 
 
 pub async fn all_reactions(req: Request) -> tide::Result {
@@ -11,38 +11,51 @@ pub async fn all_reactions(req: Request) -> tide::Result {
 
 
 pub async fn add_reaction(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let post_id = req.param("post_id")?.parse::<u32>()?;
-        let react = req.body_string().await?;
-        tide::log::debug!("react: {} (len {})", react, react.len());
-        if react.len() > 16 {
-            return Ok(StatusCode::BadRequest.into())
-        }
-        sqlx::query!(
-            "INSERT INTO reactions_user(post_id, reactor_id, reaction) VALUES (?, ?, ?)",
-            post_id, user_id, react
-        ).execute(&req.state().db).await?;
-
-        Ok(StatusCode::Ok.into())
-    } else {
-        Ok(StatusCode::Unauthorized.into())
+    let post_id = req.param("post_id")?.parse::<u32>()?;
+    let location = permissions::post_location(&req.state().db, post_id).await?;
+    let user_id = match permissions::require(&req, Permission::React, location).await? {
+        Ok(u) => u,
+        Err(s) => return Ok(s.into())
+    };
+    // missing or deleted
+    if location.forum_id.is_none() {
+        return Ok(StatusCode::NotFound.into())
+    }
+    if let Some(ban) = bans::active(&req.state().db, user_id, location).await? {
+        return Ok(bans::banned(&ban))
+    }
+    if sqlx::query!(
+        "SELECT archived `archived: bool` FROM posts INNER JOIN threads USING (thread_id) WHERE post_id = ?", post_id
+    ).fetch_optional(&req.state().db).await?.map_or(false, |r| r.archived) {
+        return Ok(permissions::forbidden("thread is archived"))
     }
+    let react = req.body_string().await?;
+    tide::log::debug!("react: {} (len {})", react, react.len());
+    if react.len() > 16 {
+        return Ok(StatusCode::BadRequest.into())
+    }
+    sqlx::query!(
+        "INSERT INTO reactions_user(post_id, reactor_id, reaction) VALUES (?, ?, ?)",
+        post_id, user_id, react
+    ).execute(&req.state().db).await?;
+
+    Ok(StatusCode::Ok.into())
 }
 
 pub async fn rem_reaction(mut req: Request) -> tide::Result {
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        let post_id = req.param("post_id")?.parse::<u32>()?;
-        let react = req.body_string().await?;
-        tide::log::debug!("react: {} (len {})", react, react.len());
-        if react.len() > 16 {
-            return Ok(StatusCode::BadRequest.into())
-        }
-        sqlx::query!(
-            "DELETE FROM reactions_user WHERE post_id = ? AND reactor_id = ? AND reaction = ?",
-            post_id, user_id, react
-        ).execute(&req.state().db).await?;
-        Ok(StatusCode::Ok.into())
-    } else {
-        Ok(StatusCode::Unauthorized.into())
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let post_id = req.param("post_id")?.parse::<u32>()?;
+    let react = req.body_string().await?;
+    tide::log::debug!("react: {} (len {})", react, react.len());
+    if react.len() > 16 {
+        return Ok(StatusCode::BadRequest.into())
     }
+    sqlx::query!(
+        "DELETE FROM reactions_user WHERE post_id = ? AND reactor_id = ? AND reaction = ?",
+        post_id, user_id, react
+    ).execute(&req.state().db).await?;
+    Ok(StatusCode::Ok.into())
 }
@@ -0,0 +1,135 @@
+use tide::{Response, StatusCode};
+use serde::Deserialize;
+
+use crate::{Request, utils::permissions, models::{Role, RolePermission, UserRole}};
+
+#[derive(Deserialize)]
+struct RoleGrant {
+    role: Role,
+    // only for category_moderator
+    category_id: Option<u32>,
+    // only for forum_moderator
+    forum_id: Option<u32>
+}
+
+#[derive(Deserialize)]
+struct RoleScope {
+    category_id: Option<u32>,
+    forum_id: Option<u32>
+}
+
+impl RoleScope {
+    fn describe(&self) -> String {
+        match (self.category_id, self.forum_id) {
+            (Some(c), _) => format!(" in category `{}`", c),
+            (None, Some(f)) => format!(" in forum `{}`", f),
+            (None, None) => String::new()
+        }
+    }
+}
+
+/// Every role and what it allows, viewable by anyone.
+pub async fn role_list(req: Request) -> tide::Result {
+    let data = sqlx::query_as!(RolePermission,
+        "SELECT role, permission FROM role_permissions ORDER BY role, permission"
+    ).fetch_all(&req.state().db).await?;
+    Ok(serde_json::to_value(data)?.into())
+}
+
+pub async fn user_roles(req: Request) -> tide::Result {
+    let user_id = req.param("user_id")?.parse::<u32>()?;
+    let data = sqlx::query_as!(UserRole,
+        "SELECT role, category_id, forum_id FROM user_roles WHERE user_id = ? ORDER BY role, category_id, forum_id",
+        user_id
+    ).fetch_all(&req.state().db).await?;
+    Ok(serde_json::to_value(data)?.into())
+}
+
+pub async fn role_grant(mut req: Request) -> tide::Result {
+    let user_id = match permissions::guarded(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let target_id = req.param("user_id")?.parse::<u32>()?;
+    let data: RoleGrant = req.body_json().await?;
+    if !data.role.is_assignable() {
+        return Ok(Response::builder(StatusCode::BadRequest).body("this role cannot be given").build())
+    }
+    if (data.role == Role::ForumModerator) != data.forum_id.is_some()
+        || (data.role == Role::CategoryModerator) != data.category_id.is_some() {
+        return Ok(Response::builder(StatusCode::BadRequest)
+            .body("a forum is needed for forum_moderator and a category for category_moderator, and only for them")
+            .build())
+    }
+    let mut tx = req.state().db.begin().await?;
+    // locks the user's rows for the role, so a grant at the same time waits and then sees this one
+    if sqlx::query!(
+        "SELECT 1 AS ex FROM user_roles WHERE user_id = ? AND role = ? AND category_id <=> ? AND forum_id <=> ?
+         FOR UPDATE",
+        target_id, data.role.as_str(), data.category_id, data.forum_id
+    ).fetch_optional(&mut tx).await?.is_some() {
+        return Ok(Response::builder(StatusCode::Conflict).body("role already held").build())
+    }
+    match sqlx::query!(
+        "INSERT INTO user_roles(user_id, role, category_id, forum_id) VALUES (?, ?, ?, ?)",
+        target_id, data.role.as_str(), data.category_id, data.forum_id
+    ).execute(&mut tx).await {
+        Ok(_) => (),
+        Err(sqlx::Error::Database(e))
+        if e.code().map_or(false, |s| s == "23000") => {
+            return Ok(Response::builder(StatusCode::Conflict)
+                .body("no such user, category or forum").build())
+        },
+        Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
+    }
+    if data.role == Role::Admin {
+        sqlx::query!("UPDATE users SET is_admin = TRUE WHERE user_id = ?", target_id)
+            .execute(&mut tx).await?;
+    }
+    let scope = RoleScope { category_id: data.category_id, forum_id: data.forum_id };
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Gave role `{}`{} to user with ID `{}`", data.role.as_str(), scope.describe(), target_id)
+    ).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::Created))
+}
+
+/// Takes `:role` away, from the container given by the `category_id` or `forum_id` query parameter
+/// for scoped roles.
+pub async fn role_revoke(req: Request) -> tide::Result {
+    let user_id = match permissions::guarded(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let target_id = req.param("user_id")?.parse::<u32>()?;
+    let role = match Role::parse(req.param("role")?) {
+        Some(r) => r,
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let scope: RoleScope = req.query()?;
+    let mut tx = req.state().db.begin().await?;
+    if role == Role::Admin && sqlx::query!(
+        "SELECT COUNT(*) `count: u32` FROM user_roles WHERE role = 'admin' AND user_id != ? FOR UPDATE",
+        target_id
+    ).fetch_one(&mut tx).await?.count == 0 {
+        return Ok(Response::builder(StatusCode::Conflict).body("cannot remove the last admin").build())
+    }
+    if sqlx::query!(
+        "DELETE FROM user_roles WHERE user_id = ? AND role = ?
+         AND category_id <=> ? AND forum_id <=> ?",
+        target_id, role.as_str(), scope.category_id, scope.forum_id
+    ).execute(&mut tx).await?.rows_affected() == 0 {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    if role == Role::Admin {
+        sqlx::query!("UPDATE users SET is_admin = FALSE WHERE user_id = ?", target_id)
+            .execute(&mut tx).await?;
+    }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Took role `{}`{} from user with ID `{}`", role.as_str(), scope.describe(), target_id)
+    ).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
@@ -7,7 +7,7 @@ use tide::convert::Serialize;
 use crate::Request;
 use crate::models::{Container, ContainerData, BasicContainer, User};
 use crate::routes::containers::PAGE_SIZE;
-use crate::utils::{data_into_hashmap, route_search, SearchQuery};
+use crate::utils::{data_into_vec, permissions, route_search, SearchQuery};
 
 
 route_search!(
@@ -15,15 +15,17 @@ route_search!(
     "SELECT category_id p_id, c.name p_name, c.description p_descr,
      forum_id c_id, f.name c_name, f.description c_descr
      FROM categories c INNER JOIN forums f USING (category_id)
-     WHERE f.name LIKE CONCAT('%', ?, '%')"
+     WHERE can_read_forum(?, forum_id) AND f.name LIKE CONCAT('%', ?, '%')
+     ORDER BY c.position, category_id, f.position, forum_id"
 );
 
 route_search!(
     topic_search,
     "SELECT forum_id p_id, f.name p_name, f.description p_descr,
      topic_id c_id, t.name c_name, t.description c_descr
-     FROM forums f INNER JOIN topics t USING (forum_id)
-     WHERE t.name LIKE CONCAT('%', ?, '%')"
+     FROM forums f INNER JOIN topics t USING (forum_id) INNER JOIN categories c USING (category_id)
+     WHERE can_read_forum(?, forum_id) AND t.deleted IS NULL AND t.name LIKE CONCAT('%', ?, '%')
+     ORDER BY c.position, category_id, f.position, forum_id, t.position, topic_id"
 );
 
 
@@ -49,7 +51,9 @@ pub async fn thread_search(req: Request) -> tide::Result {
              FROM topics top INNER JOIN threads t USING (topic_id)
              INNER JOIN posts p ON (t.thread_id = p.thread_id AND post_pos = 1)
              INNER JOIN users USING (user_id)
-             WHERE t.name LIKE CONCAT('%', ?, '%')", q
+             WHERE can_read_forum(?, top.forum_id) AND top.deleted IS NULL AND t.deleted IS NULL
+             AND t.name LIKE CONCAT('%', ?, '%')",
+            permissions::user_id(&req), q
         ).fetch(&req.state().db);
         let mut data: HashMap<u32, ContainerData<BasicContainer, ThreadAllInfo>> = HashMap::new();
         while let Some(Ok(r)) = s.next().await {
@@ -98,7 +102,11 @@ pub async fn post_search(req: Request) -> tide::Result {
              username, is_avatar_set `is_avatar_set: bool`
              FROM threads t INNER JOIN posts pf ON (t.thread_id = pf.thread_id AND post_pos = 1)
              INNER JOIN posts p ON (t.thread_id = p.thread_id) INNER JOIN users u ON (u.user_id = p.user_id)
-             WHERE p.content LIKE CONCAT('%', ?, '%') ORDER BY p.thread_id, p.post_pos", q
+             INNER JOIN topics top ON (top.topic_id = t.topic_id)
+             WHERE can_read_forum(?, top.forum_id) AND top.deleted IS NULL AND t.deleted IS NULL
+             AND p.deleted IS NULL AND p.content LIKE CONCAT('%', ?, '%')
+             ORDER BY p.thread_id, p.post_pos",
+            permissions::user_id(&req), q
         ).fetch(& req.state().db);
         let mut data: HashMap<u32, ContainerData<BasicContainer, PostSpecific>> = HashMap::new();
         while let Some(Ok(r)) = s.next().await {
@@ -0,0 +1,63 @@
+use tide::{Response, StatusCode};
+use serde::{Serialize, Deserialize};
+
+use crate::{Request, utils::{auth, permissions}, models::{ApiToken, Scope}};
+
+#[derive(Deserialize)]
+struct TokenCreate {
+    name: String,
+    scope: Scope,
+    expires_in_days: Option<u32>
+}
+
+#[derive(Serialize)]
+struct TokenCreated {
+    token_id: u64,
+    // only ever shown here, just the digest is stored
+    token: String
+}
+
+pub async fn token_list(req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data = sqlx::query_as!(ApiToken,
+        "SELECT token_id, name, scope, created, expiry, last_used
+         FROM api_tokens WHERE user_id = ? ORDER BY token_id",
+        user_id
+    ).fetch_all(&req.state().db).await?;
+    Ok(serde_json::to_value(data)?.into())
+}
+
+pub async fn token_create(mut req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: TokenCreate = req.body_json().await?;
+    let token = auth::new_token();
+    let token_id = sqlx::query!(
+        "INSERT INTO api_tokens(user_id, name, scope, token_hash, expiry)
+         VALUES (?, ?, ?, ?, NOW() + INTERVAL ? DAY)",
+        user_id, data.name, data.scope.as_str(), auth::token_digest(&token), data.expires_in_days
+    ).execute(&req.state().db).await?.last_insert_id();
+    Ok(Response::builder(StatusCode::Created)
+        .body(serde_json::to_value(TokenCreated { token_id, token })?)
+        .build())
+}
+
+pub async fn token_revoke(req: Request) -> tide::Result {
+    let user_id = match permissions::owner(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let token_id = req.param("token_id")?.parse::<u32>()?;
+    let result = sqlx::query!(
+        "DELETE FROM api_tokens WHERE token_id = ? AND user_id = ?", token_id, user_id
+    ).execute(&req.state().db).await?;
+    if result.rows_affected() == 0 {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    Ok(Response::new(StatusCode::NoContent))
+}
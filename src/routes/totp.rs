@@ -0,0 +1,237 @@
+use tide::{Response, StatusCode};
+use serde::{Serialize, Deserialize};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+use crate::{Request, utils::{wrap_error, auth, bans, permissions, throttle, totp}};
+use crate::routes::auth::{TOTP_PENDING_KEY, LoginResult, grant_session, locked_out};
+
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_BYTES: usize = 5;
+
+#[derive(Deserialize)]
+struct TotpCode {
+    code: String
+}
+
+#[derive(Serialize)]
+struct TotpEnrolment {
+    secret: String,
+    uri: String
+}
+
+#[derive(Serialize)]
+struct TotpEnabled {
+    recovery_codes: Vec<String>,
+    // set when this finished a login that was waiting on enrolment
+    login: Option<LoginResult>
+}
+
+/// Enrolment is allowed for logged in users,
+/// and for users halfway through a login that requires 2FA they do not have yet.
+fn enrolling_user(req: &Request) -> Option<u32> {
+    permissions::user_id(req)
+        .or_else(|| req.session().get::<u32>(TOTP_PENDING_KEY))
+}
+
+/// Whether `code` looks like one of the recovery codes `enrol_confirm` gives out.
+fn is_recovery_code(code: &str) -> bool {
+    code.len() == RECOVERY_CODE_BYTES * 2 && code.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Checks `code` against the user's unused recovery codes, using it up if it matches.
+async fn use_recovery_code(req: &Request, user_id: u32, code: &str) -> tide::Result<bool> {
+    let codes = sqlx::query!(
+        "SELECT code_id, code_hash, credentials `creds: Vec<u8>`, salt `salt: Vec<u8>`
+         FROM totp_recovery_codes WHERE user_id = ? AND NOT used",
+        user_id
+    ).fetch_all(&req.state().db).await?;
+    for c in codes {
+        match auth::verify_stored(
+            code.trim(),
+            c.code_hash.as_deref(),
+            c.creds.as_deref().zip(c.salt.as_deref())
+        ) {
+            Ok(_) => {
+                sqlx::query!("UPDATE totp_recovery_codes SET used = TRUE WHERE code_id = ?", c.code_id)
+                    .execute(&req.state().db).await?;
+                return Ok(true)
+            },
+            Err(auth::PASSWORD_ERROR) => (),
+            Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
+        }
+    }
+    Ok(false)
+}
+
+/// Checks a TOTP code, falling back to recovery codes.
+async fn check_code(req: &Request, user_id: u32, secret: &[u8], last_step: Option<u64>, code: &str)
+    -> tide::Result<bool> {
+    if let Some(step) = totp::verify(secret, code, last_step) {
+        sqlx::query!("UPDATE users SET totp_last_step = ? WHERE user_id = ?", step, user_id)
+            .execute(&req.state().db).await?;
+        return Ok(true)
+    }
+    // each recovery code is a password hash to verify, so they are not tried for anything else
+    if !is_recovery_code(code.trim()) {
+        return Ok(false)
+    }
+    use_recovery_code(req, user_id, code).await
+}
+
+pub async fn enrol_start(req: Request) -> tide::Result {
+    if let Some(user_id) = enrolling_user(&req) {
+        let r = sqlx::query!(
+            "SELECT username, totp_enabled `totp_enabled: bool` FROM users WHERE user_id = ?",
+            user_id
+        ).fetch_one(&req.state().db).await?;
+        if r.totp_enabled {
+            return Ok(Response::builder(StatusCode::Conflict).body("2fa already enabled").build())
+        }
+        let secret = totp::new_secret();
+        sqlx::query!(
+            "UPDATE users SET totp_secret = ?, totp_last_step = NULL WHERE user_id = ?",
+            secret, user_id
+        ).execute(&req.state().db).await?;
+        Ok(serde_json::to_value(TotpEnrolment {
+            secret: totp::encode_secret(&secret),
+            uri: totp::provisioning_uri(&r.username, &secret)
+        })?.into())
+    } else {
+        Ok(permissions::unauthorized())
+    }
+}
+
+pub async fn enrol_confirm(mut req: Request) -> tide::Result {
+    if let Some(user_id) = enrolling_user(&req) {
+        let data: TotpCode = req.body_json().await?;
+        let r = sqlx::query!(
+            "SELECT totp_secret, totp_enabled `totp_enabled: bool`, is_admin `is_admin: bool`
+             FROM users WHERE user_id = ?",
+            user_id
+        ).fetch_one(&req.state().db).await?;
+        if r.totp_enabled {
+            return Ok(Response::builder(StatusCode::Conflict).body("2fa already enabled").build())
+        }
+        let secret: Vec<u8> = match r.totp_secret {
+            Some(s) => s,
+            None => return Ok(Response::builder(StatusCode::BadRequest).body("2fa enrolment not started").build())
+        };
+        let step = match totp::verify(&secret, &data.code, None) {
+            Some(s) => s,
+            None => return Ok(Response::builder(StatusCode::Forbidden).body("incorrect code").build())
+        };
+
+        let mut recovery_codes = Vec::with_capacity(RECOVERY_CODES);
+        let mut tx = req.state().db.begin().await?;
+        sqlx::query!(
+            "UPDATE users SET totp_enabled = TRUE, totp_last_step = ? WHERE user_id = ?",
+            step, user_id
+        ).execute(&mut tx).await?;
+        sqlx::query!("DELETE FROM totp_recovery_codes WHERE user_id = ?", user_id)
+            .execute(&mut tx).await?;
+        for _ in 0..RECOVERY_CODES {
+            let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+            OsRng.fill_bytes(&mut bytes);
+            let code = hex::encode(bytes);
+            // recovery codes are as good as passwords, so they are stored the same way
+            let code_hash = wrap_error!(auth::hash(&code), StatusCode::InternalServerError);
+            sqlx::query!(
+                "INSERT INTO totp_recovery_codes(user_id, code_hash) VALUES (?, ?)",
+                user_id, code_hash
+            ).execute(&mut tx).await?;
+            recovery_codes.push(code);
+        }
+        tx.commit().await?;
+
+        let mut login = None;
+        if req.session().get::<u32>(TOTP_PENDING_KEY).is_some() {
+            grant_session(req.session_mut(), user_id)?;
+            login = Some(LoginResult { user_id, is_admin: r.is_admin });
+        }
+        Ok(Response::builder(StatusCode::Created)
+            .body(serde_json::to_value(TotpEnabled { recovery_codes, login })?)
+            .build())
+    } else {
+        Ok(permissions::unauthorized())
+    }
+}
+
+pub async fn login_totp(mut req: Request) -> tide::Result {
+    let user_id = match req.session().get::<u32>(TOTP_PENDING_KEY) {
+        Some(u) => u,
+        None => return Ok(Response::builder(StatusCode::Unauthorized).body("password login required").build())
+    };
+    let data: TotpCode = req.body_json().await?;
+    let r = match sqlx::query!(
+        "SELECT username, totp_secret `totp_secret!`, totp_last_step, is_admin `is_admin: bool`
+         FROM users WHERE user_id = ? AND totp_enabled",
+        user_id
+    ).fetch_optional(&req.state().db).await? {
+        Some(r) => r,
+        None => return Ok(Response::builder(StatusCode::Forbidden).body("2fa enrolment required").build())
+    };
+    // codes are only 6 digits, so they need the same throttling as passwords
+    let ip = throttle::client_ip(&req);
+    if throttle::is_locked(&req.state().db, &r.username, &ip).await? {
+        return Ok(locked_out())
+    }
+    if !check_code(&req, user_id, &r.totp_secret, r.totp_last_step, &data.code).await? {
+        throttle::login_failed(&req.state().db, &r.username, &ip, "2fa").await?;
+        return Ok(Response::builder(StatusCode::Forbidden).body("incorrect code").build())
+    }
+    throttle::clear(&req.state().db, throttle::Kind::Account, &r.username).await?;
+    if let Some(ban) = bans::permanent(&req.state().db, user_id).await? {
+        return Ok(bans::banned(&ban))
+    }
+    grant_session(req.session_mut(), user_id)?;
+    Ok(serde_json::to_value(LoginResult { user_id, is_admin: r.is_admin })?.into())
+}
+
+pub async fn disable(mut req: Request) -> tide::Result {
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let data: TotpCode = req.body_json().await?;
+    let r = sqlx::query!(
+        "SELECT totp_secret, totp_last_step, totp_enabled `totp_enabled: bool`,
+         totp_required `totp_required: bool` FROM users WHERE user_id = ?",
+        user_id
+    ).fetch_one(&req.state().db).await?;
+    if r.totp_required {
+        return Ok(Response::builder(StatusCode::Forbidden).body("2fa is required for this account").build())
+    }
+    let secret: Vec<u8> = match r.totp_secret {
+        Some(s) if r.totp_enabled => s,
+        _ => return Ok(Response::builder(StatusCode::BadRequest).body("2fa not enabled").build())
+    };
+    if !check_code(&req, user_id, &secret, r.totp_last_step, &data.code).await? {
+        return Ok(Response::builder(StatusCode::Forbidden).body("incorrect code").build())
+    }
+    let mut tx = req.state().db.begin().await?;
+    sqlx::query!(
+        "UPDATE users SET totp_enabled = FALSE, totp_secret = NULL, totp_last_step = NULL
+         WHERE user_id = ?",
+        user_id
+    ).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM totp_recovery_codes WHERE user_id = ?", user_id)
+        .execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_recovery_code_matches_issued_codes_only() {
+        let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        assert!(is_recovery_code(&hex::encode(bytes)));
+        assert!(!is_recovery_code("123456"));
+        assert!(!is_recovery_code("0123456789a"));
+        assert!(!is_recovery_code("0123456789 "));
+        assert!(!is_recovery_code("012345678g"));
+    }
+}
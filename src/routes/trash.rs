@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use sqlx::{MySql, Pool};
+use tide::{Response, StatusCode};
+
+use crate::{Request, models::{Permission, TrashItem}, utils::{permissions::{self, Location}, trash}};
+
+/// Whether the user can restore at `location`, asking the database once per location and permission.
+async fn allowed(
+    pool: &Pool<MySql>, user_id: u32, seen: &mut HashMap<(&'static str, Option<u32>, Option<u32>), bool>,
+    permission: Permission, location: Location
+) -> sqlx::Result<bool> {
+    let key = (permission.as_str(), location.category_id, location.forum_id);
+    if let Some(a) = seen.get(&key) {
+        return Ok(*a)
+    }
+    let a = permissions::has(pool, Some(user_id), permission, location).await?;
+    seen.insert(key, a);
+    Ok(a)
+}
+
+/// Everything deleted that the user could restore, most recently deleted first.
+/// Containers need `manage_containers` where they were, threads and posts `moderate_posts`.
+pub async fn trash_list(req: Request) -> tide::Result {
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let pool = &req.state().db;
+    let days = *trash::RETENTION_DAYS;
+    let mut seen = HashMap::new();
+    let mut items = vec![];
+
+    for r in sqlx::query!(
+        "SELECT category_id id, name, deleted `deleted!`, deleted_by FROM categories
+         WHERE deleted > NOW() - INTERVAL ? DAY", days
+    ).fetch_all(pool).await? {
+        if allowed(pool, user_id, &mut seen, Permission::ManageContainers, Location::GLOBAL).await? {
+            items.push(TrashItem {
+                kind: "category", id: r.id, name: r.name,
+                deleted: r.deleted, deleted_by: r.deleted_by, purge_after: trash::purge_after(r.deleted)
+            });
+        }
+    }
+    for r in sqlx::query!(
+        "SELECT forum_id id, name, category_id, deleted `deleted!`, deleted_by FROM forums
+         WHERE deleted > NOW() - INTERVAL ? DAY", days
+    ).fetch_all(pool).await? {
+        let location = Location::category(r.category_id);
+        if allowed(pool, user_id, &mut seen, Permission::ManageContainers, location).await? {
+            items.push(TrashItem {
+                kind: "forum", id: r.id, name: r.name,
+                deleted: r.deleted, deleted_by: r.deleted_by, purge_after: trash::purge_after(r.deleted)
+            });
+        }
+    }
+    for r in sqlx::query!(
+        "SELECT topic_id id, t.name, category_id, forum_id, t.deleted `deleted!`, t.deleted_by
+         FROM topics t INNER JOIN forums USING (forum_id)
+         WHERE t.deleted > NOW() - INTERVAL ? DAY", days
+    ).fetch_all(pool).await? {
+        let location = Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) };
+        if allowed(pool, user_id, &mut seen, Permission::ManageContainers, location).await? {
+            items.push(TrashItem {
+                kind: "topic", id: r.id, name: r.name,
+                deleted: r.deleted, deleted_by: r.deleted_by, purge_after: trash::purge_after(r.deleted)
+            });
+        }
+    }
+    for r in sqlx::query!(
+        "SELECT thread_id id, th.name, category_id, forum_id, th.deleted `deleted!`, th.deleted_by
+         FROM threads th INNER JOIN topics USING (topic_id) INNER JOIN forums USING (forum_id)
+         WHERE th.deleted > NOW() - INTERVAL ? DAY", days
+    ).fetch_all(pool).await? {
+        let location = Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) };
+        if allowed(pool, user_id, &mut seen, Permission::ModeratePosts, location).await? {
+            items.push(TrashItem {
+                kind: "thread", id: r.id, name: r.name,
+                deleted: r.deleted, deleted_by: r.deleted_by, purge_after: trash::purge_after(r.deleted)
+            });
+        }
+    }
+    for r in sqlx::query!(
+        "SELECT post_id id, LEFT(p.content, 100) `name!`, category_id, forum_id, p.deleted `deleted!`, p.deleted_by
+         FROM posts p INNER JOIN threads USING (thread_id) INNER JOIN topics USING (topic_id)
+         INNER JOIN forums USING (forum_id)
+         WHERE p.deleted > NOW() - INTERVAL ? DAY", days
+    ).fetch_all(pool).await? {
+        let location = Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) };
+        if allowed(pool, user_id, &mut seen, Permission::ModeratePosts, location).await? {
+            items.push(TrashItem {
+                kind: "post", id: r.id, name: r.name,
+                deleted: r.deleted, deleted_by: r.deleted_by, purge_after: trash::purge_after(r.deleted)
+            });
+        }
+    }
+
+    items.sort_by(|a, b| b.deleted.cmp(&a.deleted));
+    Ok(serde_json::to_value(items)?.into())
+}
+
+/// Finishes a restore that changed `rows` rows, none meaning it was not deleted or is past the retention window.
+async fn restored(req: &Request, user_id: u32, kind: &str, id: u32, rows: u64) -> tide::Result {
+    if rows == 0 {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Restored {} with ID `{}`", kind, id)
+    ).execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+pub async fn category_restore(req: Request) -> tide::Result {
+    let category_id: u32 = req.param("category_id")?.parse()?;
+    let user_id = match permissions::guarded(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let rows = sqlx::query!(
+        "UPDATE categories SET deleted = NULL, deleted_by = NULL
+         WHERE category_id = ? AND deleted > NOW() - INTERVAL ? DAY",
+        category_id, *trash::RETENTION_DAYS
+    ).execute(&req.state().db).await?.rows_affected();
+    restored(&req, user_id, "category", category_id, rows).await
+}
+
+pub async fn forum_restore(req: Request) -> tide::Result {
+    let forum_id: u32 = req.param("forum_id")?.parse()?;
+    let location = match sqlx::query!(
+        "SELECT category_id FROM forums WHERE forum_id = ? AND deleted > NOW() - INTERVAL ? DAY",
+        forum_id, *trash::RETENTION_DAYS
+    ).fetch_optional(&req.state().db).await? {
+        Some(r) => Location::category(r.category_id),
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let rows = sqlx::query!(
+        "UPDATE forums SET deleted = NULL, deleted_by = NULL WHERE forum_id = ? AND deleted IS NOT NULL", forum_id
+    ).execute(&req.state().db).await?.rows_affected();
+    restored(&req, user_id, "forum", forum_id, rows).await
+}
+
+pub async fn topic_restore(req: Request) -> tide::Result {
+    let topic_id: u32 = req.param("topic_id")?.parse()?;
+    let location = match sqlx::query!(
+        "SELECT category_id, forum_id FROM topics t INNER JOIN forums USING (forum_id)
+         WHERE topic_id = ? AND t.deleted > NOW() - INTERVAL ? DAY",
+        topic_id, *trash::RETENTION_DAYS
+    ).fetch_optional(&req.state().db).await? {
+        Some(r) => Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) },
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let user_id = match permissions::require(&req, Permission::ManageContainers, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let rows = sqlx::query!(
+        "UPDATE topics SET deleted = NULL, deleted_by = NULL WHERE topic_id = ? AND deleted IS NOT NULL", topic_id
+    ).execute(&req.state().db).await?.rows_affected();
+    restored(&req, user_id, "topic", topic_id, rows).await
+}
+
+pub async fn thread_restore(req: Request) -> tide::Result {
+    let thread_id: u32 = req.param("thread_id")?.parse()?;
+    let location = match sqlx::query!(
+        "SELECT category_id, forum_id FROM threads th INNER JOIN topics USING (topic_id) INNER JOIN forums USING (forum_id)
+         WHERE thread_id = ? AND th.deleted > NOW() - INTERVAL ? DAY",
+        thread_id, *trash::RETENTION_DAYS
+    ).fetch_optional(&req.state().db).await? {
+        Some(r) => Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) },
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let user_id = match permissions::require(&req, Permission::ModeratePosts, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let rows = sqlx::query!(
+        "UPDATE threads SET deleted = NULL, deleted_by = NULL WHERE thread_id = ? AND deleted IS NOT NULL", thread_id
+    ).execute(&req.state().db).await?.rows_affected();
+    restored(&req, user_id, "thread", thread_id, rows).await
+}
+
+pub async fn post_restore(req: Request) -> tide::Result {
+    let post_id: u32 = req.param("post_id")?.parse()?;
+    let location = match sqlx::query!(
+        "SELECT category_id, forum_id FROM posts p INNER JOIN threads USING (thread_id)
+         INNER JOIN topics USING (topic_id) INNER JOIN forums USING (forum_id)
+         WHERE post_id = ? AND p.deleted > NOW() - INTERVAL ? DAY",
+        post_id, *trash::RETENTION_DAYS
+    ).fetch_optional(&req.state().db).await? {
+        Some(r) => Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) },
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let user_id = match permissions::require(&req, Permission::ModeratePosts, location).await? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let rows = sqlx::query!(
+        "UPDATE posts SET deleted = NULL, deleted_by = NULL WHERE post_id = ? AND deleted IS NOT NULL", post_id
+    ).execute(&req.state().db).await?.rows_affected();
+    restored(&req, user_id, "post", post_id, rows).await
+}
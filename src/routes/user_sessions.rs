@@ -0,0 +1,56 @@
+use tide::{Response, StatusCode};
+
+use crate::{Request, utils::{permissions, sessions}, models::{Permission, SessionInfo}};
+
+pub async fn session_list(req: Request) -> tide::Result {
+    let (_, target_id) = match permissions::owner_or(&req, Permission::ManageUsers).await? {
+        Ok(ids) => ids,
+        Err(resp) => return Ok(resp)
+    };
+    let data = sqlx::query_as!(SessionInfo,
+        "SELECT session_no, created, last_seen, ip, user_agent, sess_id = ? AS `current: bool`
+         FROM sessions WHERE user_id = ? AND (expiry IS NULL OR expiry > NOW())
+         ORDER BY last_seen DESC",
+        req.session().id(), target_id
+    ).fetch_all(&req.state().db).await?;
+    Ok(serde_json::to_value(data)?.into())
+}
+
+pub async fn session_revoke(req: Request) -> tide::Result {
+    let (user_id, target_id) = match permissions::owner_or(&req, Permission::ManageUsers).await? {
+        Ok(ids) => ids,
+        Err(resp) => return Ok(resp)
+    };
+    let session_no = req.param("session_no")?.parse::<u32>()?;
+    let result = sqlx::query!(
+        "DELETE FROM sessions WHERE session_no = ? AND user_id = ?", session_no, target_id
+    ).execute(&req.state().db).await?;
+    if result.rows_affected() == 0 {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    if user_id != target_id {
+        sqlx::query!(
+            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+            user_id, format!("Revoked session {} of user with ID `{}`", session_no, target_id)
+        ).execute(&req.state().db).await?;
+    }
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+/// Logs out everywhere else when done by the user, or everywhere when done by an admin.
+pub async fn session_revoke_all(req: Request) -> tide::Result {
+    let (user_id, target_id) = match permissions::owner_or(&req, Permission::ManageUsers).await? {
+        Ok(ids) => ids,
+        Err(resp) => return Ok(resp)
+    };
+    if user_id == target_id {
+        sessions::clear_user_sessions(&req.state().db, user_id, Some(req.session().id())).await?;
+    } else {
+        sessions::clear_user_sessions(&req.state().db, target_id, None).await?;
+        sqlx::query!(
+            "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+            user_id, format!("Force logged out user with ID `{}`", target_id)
+        ).execute(&req.state().db).await?;
+    }
+    Ok(Response::new(StatusCode::NoContent))
+}
@@ -1,8 +1,12 @@
 use tide::{Response, StatusCode};
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 use serde_json;
 
-use crate::{Request, utils::route_get, models::{User, Log}};
+use crate::{Request, utils::{auth, bans, email, export, permissions::{self, Location}, validation}, models::{Ban, User, Log, Permission, Role}};
+use crate::routes::images;
+
+/// Posts of deleted users are moved to this account, which cannot be logged into.
+pub(crate) const DELETED_USERNAME: &str = "[deleted]";
 
 #[derive(Deserialize)]
 struct UsernameQuery {
@@ -10,8 +14,14 @@ struct UsernameQuery {
 }
 
 pub async fn available_username(req: Request) -> tide::Result {
+    let username = validation::normalize_username(&req.query::<UsernameQuery>()?.username);
+    let violations = validation::check_username(&username);
+    if !violations.is_empty() {
+        return validation::unprocessable(violations)
+    }
     match sqlx::query!(
-        "SELECT 1 AS available FROM users WHERE username = ?", req.query::<UsernameQuery>()?.username
+        "SELECT 1 AS available FROM users WHERE username_skeleton = ?",
+        validation::username_skeleton(&username)
     ).fetch_optional(&req.state().db).await? {
         Some(_) => Ok(Response::new(StatusCode::Conflict)),
         None => Ok("".into())
@@ -27,31 +37,69 @@ pub async fn get(req: Request) -> tide::Result {
         .fetch_one(&req.state().db).await?;
     Ok(serde_json::to_string(&user)?.into())
 }*/
-route_get!(
-    user_get, req, User,
-    "SELECT user_id, username, description, profile_tag,
-     is_avatar_set AS `is_avatar_set: _`, is_admin AS `is_admin: _`
-     FROM users WHERE user_id = ?",
-    req.param("user_id")?.parse::<u32>()?
-);
+#[derive(Serialize)]
+struct Profile {
+    #[serde(flatten)]
+    user: User,
+    // ones in force, so others can see why someone is not posting
+    bans: Vec<Ban>
+}
+
+pub async fn user_get(req: Request) -> tide::Result {
+    let user_id = req.param("user_id")?.parse::<u32>()?;
+    if let Some(user) = sqlx::query_as!(User,
+        "SELECT user_id, username, description, profile_tag,
+         is_avatar_set AS `is_avatar_set: _`, is_admin AS `is_admin: _`
+         FROM users WHERE user_id = ?",
+        user_id
+    ).fetch_optional(&req.state().db).await? {
+        let bans = bans::all_active(&req.state().db, user_id).await?;
+        return Ok(serde_json::to_value(Profile { user, bans })?.into())
+    }
+    Ok(StatusCode::NotFound.into())
+}
 
 #[derive(Deserialize)]
 struct UserPatch {
     profile_tag: Option<String>,
-    description: Option<String>
+    description: Option<String>,
+    // changing this makes it unverified until the new address is verified
+    email: Option<String>
 }
 
 pub async fn user_patch(mut req: Request) -> tide::Result {
     let data: UserPatch = req.body_json().await?;
-    if let Some(user_id) = req.session().get::<u32>("user_id") {
-        sqlx::query!(
-            "UPDATE users SET profile_tag = COALESCE(?, profile_tag),
-             description = COALESCE(?, description) WHERE user_id = ?",
-            data.profile_tag, data.description, user_id
-        ).execute(&req.state().db).await?;
-        return Ok(Response::new(StatusCode::NoContent));
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    if let Some(ban) = bans::active(&req.state().db, user_id, Location::GLOBAL).await? {
+        return Ok(bans::banned(&ban))
     }
-    Ok(Response::new(StatusCode::Unauthorized))
+    if let Some(e) = data.email.as_deref().map(str::trim) {
+        let violations = validation::check_email(e);
+        if !violations.is_empty() {
+            return validation::unprocessable(violations)
+        }
+        match sqlx::query!(
+            "UPDATE users SET email = ?, email_verified = FALSE WHERE user_id = ? AND NOT email <=> ?",
+            e, user_id, e
+        ).execute(&req.state().db).await {
+            Ok(r) if r.rows_affected() != 0 => email::send_verification(req.state(), user_id, e).await?,
+            Ok(_) => (),
+            Err(sqlx::Error::Database(err))
+            if err.code().map_or(false, |s| s == "23000") => return validation::unprocessable(vec![
+                validation::Violation::new("email", "taken", "is already in use")
+            ]),
+            Err(err) => return Err(tide::Error::new(StatusCode::InternalServerError, err))
+        }
+    }
+    sqlx::query!(
+        "UPDATE users SET profile_tag = COALESCE(?, profile_tag),
+         description = COALESCE(?, description) WHERE user_id = ?",
+        data.profile_tag, data.description, user_id
+    ).execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
 }
 
 pub async fn log_get(req: Request) -> tide::Result {
@@ -59,10 +107,127 @@ pub async fn log_get(req: Request) -> tide::Result {
     let data = sqlx::query_as!(Log,
         "SELECT log_id, log, time FROM audit_log WHERE user_id = ? ORDER BY log_id DESC",
         user_id).fetch_all(&req.state().db).await?;
-    if data.len() != 0 || sqlx::query!(
-        "SELECT is_admin AS `is_admin: bool` FROM users WHERE user_id = ?", user_id
-    ).fetch_one(&req.state().db).await?.is_admin {
+    if data.len() != 0 || permissions::has_role(&req.state().db, user_id, Role::Admin).await? {
         return Ok(serde_json::to_value(data)?.into())
     }
     Ok(Response::new(StatusCode::BadRequest))
 }
+
+/// Failed logins and lockouts, which are not tied to a user. Needs `view_logs`, checked on the route.
+pub async fn security_log_get(req: Request) -> tide::Result {
+    let data = sqlx::query_as!(Log,
+        "SELECT log_id, log, time FROM audit_log WHERE user_id IS NULL ORDER BY log_id DESC LIMIT 500"
+    ).fetch_all(&req.state().db).await?;
+    Ok(serde_json::to_value(data)?.into())
+}
+
+#[derive(Deserialize)]
+struct TotpRequirement {
+    required: bool
+}
+
+pub async fn require_totp(mut req: Request) -> tide::Result {
+    let user_id = match permissions::guarded(&req)? {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let target_id = req.param("user_id")?.parse::<u32>()?;
+    let data: TotpRequirement = req.body_json().await?;
+    if sqlx::query!("SELECT 1 AS ex FROM users WHERE user_id = ?", target_id)
+        .fetch_optional(&req.state().db).await?.is_none() {
+        return Ok(Response::new(StatusCode::NotFound))
+    }
+    if !permissions::has_role(&req.state().db, target_id, Role::Admin).await? {
+        return Ok(Response::builder(StatusCode::BadRequest)
+            .body("2fa can only be required for admins").build())
+    }
+    sqlx::query!("UPDATE users SET totp_required = ? WHERE user_id = ?", data.required, target_id)
+        .execute(&req.state().db).await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        user_id, format!("Set 2FA required to {} for user with ID `{}`", data.required, target_id)
+    ).execute(&req.state().db).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[derive(Deserialize)]
+struct DeleteConfirm {
+    // of the user doing the deleting, which is not the deleted user when done by an admin
+    password: String
+}
+
+pub async fn user_delete(mut req: Request) -> tide::Result {
+    let user_id = match permissions::authenticated(&req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(resp)
+    };
+    let target_id = req.param("user_id")?.parse::<u32>()?;
+    let data: DeleteConfirm = req.body_json().await?;
+    let actor = sqlx::query!(
+        "SELECT password_hash, credentials `creds: Vec<u8>`, salt `salt: Vec<u8>` FROM users WHERE user_id = ?",
+        user_id
+    ).fetch_one(&req.state().db).await?;
+    if user_id != target_id
+        && !permissions::has(&req.state().db, Some(user_id), Permission::ManageUsers, Location::GLOBAL).await? {
+        return Ok(permissions::forbidden("missing permission `manage_users`"))
+    }
+    match auth::verify_stored(
+        &data.password,
+        actor.password_hash.as_deref(),
+        actor.creds.as_deref().zip(actor.salt.as_deref())
+    ) {
+        Ok(_) => (),
+        Err(auth::PASSWORD_ERROR) => return Ok(
+            Response::builder(StatusCode::Forbidden).body("incorrect password").build()),
+        Err(e) => return Err(tide::Error::new(StatusCode::InternalServerError, e))
+    }
+
+    let mut tx = req.state().db.begin().await?;
+    let target = match sqlx::query!(
+        "SELECT username FROM users WHERE user_id = ? FOR UPDATE", target_id
+    ).fetch_optional(&mut tx).await? {
+        Some(t) if t.username != DELETED_USERNAME => t,
+        Some(_) => return Ok(permissions::forbidden("the deleted user cannot be deleted")),
+        None => return Ok(Response::new(StatusCode::NotFound))
+    };
+    let tombstone_id = sqlx::query!(
+        "SELECT user_id FROM users WHERE username = ?", DELETED_USERNAME
+    ).fetch_one(&mut tx).await?.user_id;
+
+    // thread and post positions stay as they are, only the author changes
+    sqlx::query!("UPDATE posts SET user_id = ? WHERE user_id = ?", tombstone_id, target_id)
+        .execute(&mut tx).await?;
+    sqlx::query!("UPDATE audit_log SET user_id = ? WHERE user_id = ?", tombstone_id, target_id)
+        .execute(&mut tx).await?;
+    sqlx::query!("UPDATE post_revisions SET editor_id = ? WHERE editor_id = ?", tombstone_id, target_id)
+        .execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM reactions_user WHERE reactor_id = ?", target_id)
+        .execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM sessions WHERE user_id = ?", target_id)
+        .execute(&mut tx).await?;
+    sqlx::query!(
+        "DELETE FROM login_failures WHERE kind = 'account' AND key_value = ?", target.username
+    ).execute(&mut tx).await?;
+    let exports = sqlx::query!("SELECT export_id FROM data_exports WHERE user_id = ?", target_id)
+        .fetch_all(&mut tx).await?;
+    // credentials, tokens, resets, recovery codes, exports, linked identities and roles go with the row
+    sqlx::query!("DELETE FROM users WHERE user_id = ?", target_id)
+        .execute(&mut tx).await?;
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (?, ?)",
+        if user_id == target_id { None } else { Some(user_id) },
+        format!("Deleted account `{}` with ID `{}`", target.username, target_id)
+    ).execute(&mut tx).await?;
+    tx.commit().await?;
+
+    images::remove_avatar(target_id).await?;
+    for r in exports {
+        if let Err(e) = async_std::fs::remove_file(export::export_path(r.export_id)).await {
+            tide::log::warn!("failed to delete data export of deleted user: {:?}", e);
+        }
+    }
+    if user_id == target_id {
+        req.session_mut().destroy();
+    }
+    Ok(Response::new(StatusCode::NoContent))
+}
@@ -1,88 +1,166 @@
 use argon2::password_hash::{
-    errors::Error, rand_core::{self, RngCore}, ParamsString,
+    errors::Error, rand_core::{self, RngCore}, Ident, ParamsString,
     PasswordHash, PasswordHasher, PasswordVerifier, SaltString, Output};
 use lazy_static::lazy_static;
 use hex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::env;
 
+use crate::utils::env_or;
+
 const ALGO: argon2::Algorithm = argon2::Algorithm::Argon2id;
 const VER: argon2::Version = argon2::Version::V0x13;
 const HASH_LEN: usize = 64;
 const SALT_LEN: usize = 48;
 
+// hashes from before PHC strings were stored all used these, with pepper version 1
+const LEGACY_M_COST: u32 = 4096;
+const LEGACY_T_COST: u32 = 12;
+const LEGACY_P_COST: u32 = 4;
+
+fn decode_secret(name: &str, value: String) -> Vec<u8> {
+    hex::decode(value).unwrap_or_else(|_| panic!("{} env var cannot be decoded into bytes", name))
+}
+
 lazy_static! {
-    static ref SECRET: Vec<u8> = hex::decode(
-        env::var("PASS_SECRET").expect("PASS_SECRET env var should be set")
-    ).expect("secret env var cannot be decoded into bytes");
-
-    static ref A2: argon2::Argon2<'static> = argon2::Argon2::new_with_secret(
-        &SECRET.as_slice(),
-        ALGO,
-        VER,
-        argon2::Params::new(
-            4096,
-            12,
-            4,
-            Some(HASH_LEN)
-        ).expect("argon2 hasher params valid input")
-    ).expect("argon2 hasher creation failure");
+    /// Version of the current pepper `PASS_SECRET`.
+    /// Older peppers stay usable for verifying as `PASS_SECRET_<version>`.
+    static ref PEPPER_VERSION: u32 = env_or("PASS_SECRET_VERSION", 1);
+
+    static ref PEPPERS: HashMap<u32, Vec<u8>> = {
+        let mut peppers = HashMap::new();
+        for (name, value) in env::vars() {
+            if let Some(Ok(version)) = name.strip_prefix("PASS_SECRET_").map(str::parse::<u32>) {
+                peppers.insert(version, decode_secret(&name, value));
+            }
+        }
+        peppers.insert(*PEPPER_VERSION, decode_secret(
+            "PASS_SECRET",
+            env::var("PASS_SECRET").expect("PASS_SECRET env var should be set")
+        ));
+        peppers
+    };
+
+    /// Parameters new hashes are made with. Raising these makes old hashes get rehashed on login.
+    static ref PARAMS: argon2::Params = argon2::ParamsBuilder::new()
+        .m_cost(env_or("ARGON2_M_COST", LEGACY_M_COST))
+        .t_cost(env_or("ARGON2_T_COST", LEGACY_T_COST))
+        .p_cost(env_or("ARGON2_P_COST", LEGACY_P_COST))
+        .output_len(HASH_LEN)
+        .keyid(argon2::KeyId::new(&PEPPER_VERSION.to_be_bytes()).expect("pepper version fits in a key id"))
+        .build()
+        .expect("argon2 hasher params valid input");
+
+    /// One hasher per pepper version.
+    /// Verifying takes its parameters from the hash, so these only differ in the secret.
+    static ref HASHERS: HashMap<u32, argon2::Argon2<'static>> = PEPPERS.iter()
+        .map(|(version, secret)| (*version, argon2::Argon2::new_with_secret(
+            secret.as_slice(), ALGO, VER, PARAMS.clone()
+        ).expect("argon2 hasher creation failure")))
+        .collect();
+
+    static ref LEGACY_PARAMS: argon2::Params = argon2::Params::new(
+        LEGACY_M_COST,
+        LEGACY_T_COST,
+        LEGACY_P_COST,
+        Some(HASH_LEN)
+    ).expect("argon2 hasher params valid input");
+
+    // verified against when a username does not exist, so that takes as long as a wrong password
+    static ref DUMMY: String = hash("dummy password").expect("dummy password hashes");
 }
 
 pub(crate) type AuthError = Error;
 pub(crate) const PASSWORD_ERROR: AuthError = Error::Password;
 
-pub(crate) struct Hashed {
-    pub hash: [u8; HASH_LEN],
-    pub salt: [u8; SALT_LEN]
+fn pepper_version(keyid: &[u8]) -> u32 {
+    match keyid.try_into() {
+        Ok(bytes) => u32::from_be_bytes(bytes),
+        Err(_) => 1
+    }
 }
 
-impl Hashed {
-    pub fn new(hash: [u8; HASH_LEN], salt: [u8; SALT_LEN]) -> Self {
-        Self {
-            hash,
-            salt
-        }
-    }
+fn needs_rehash(hash: &PasswordHash, params: &argon2::Params) -> bool {
+    let algo: Ident = ALGO.into();
+    hash.algorithm != algo
+        || hash.version != Some(VER.into())
+        || params.m_cost() != PARAMS.m_cost()
+        || params.t_cost() != PARAMS.t_cost()
+        || params.p_cost() != PARAMS.p_cost()
+        || params.output_len() != PARAMS.output_len()
+        || params.keyid() != PARAMS.keyid()
+}
 
-    pub fn new_check_length(hash: &[u8], salt: &[u8]) -> Self {
-        Self {
-            hash: hash.try_into().expect(
-                &format!("Incorrect hash length! Expected length {}, got length {}",
-                         HASH_LEN, hash.len())),
-            salt: salt.try_into().expect(
-                &format!("Incorrect salt length! Expected length {}, got length {}",
-                         SALT_LEN, salt.len()))
+/// Hashes with the current parameters and pepper, returning a PHC string.
+pub(crate) fn hash(password: &str) -> Result<String, Error> {
+    let mut salt_bytes = [0u8; SALT_LEN];
+    rand_core::OsRng.fill_bytes(&mut salt_bytes);
+    let salt_str = SaltString::encode_b64(&salt_bytes)?;
+    let hasher = HASHERS.get(&*PEPPER_VERSION).expect("current pepper has a hasher");
+    Ok(hasher.hash_password(password.as_bytes(), salt_str.as_salt())?.to_string())
+}
+
+/// Verifies against a PHC string from `hash`.
+/// On success, returns whether the hash is outdated and should be replaced.
+pub(crate) fn verify(password: &str, phc: &str) -> Result<bool, AuthError> {
+    let hash = PasswordHash::new(phc)?;
+    let params = argon2::Params::try_from(&hash)?;
+    let version = pepper_version(params.keyid());
+    let hasher = match HASHERS.get(&version) {
+        Some(h) => h,
+        None => {
+            tide::log::error!("no PASS_SECRET_{} set for a stored hash", version);
+            return Err(Error::Crypto)
         }
-    }
+    };
+    hasher.verify_password(password.as_bytes(), &hash)?;
+    Ok(needs_rehash(&hash, &params))
+}
 
-    pub fn get_salt_string(&self) -> Result<SaltString, AuthError> {
-        SaltString::encode_b64(&self.salt)
+/// Verifies against a raw hash and salt stored before PHC strings were.
+fn verify_legacy(password: &str, hash: &[u8], salt: &[u8]) -> Result<(), AuthError> {
+    let salt_str = SaltString::encode_b64(salt)?;
+    let hasher = HASHERS.get(&1).ok_or(Error::Crypto)?;
+    hasher.verify_password(password.as_bytes(), &PasswordHash {
+        algorithm: ALGO.into(),
+        version: Some(VER.into()),
+        params: ParamsString::try_from(&*LEGACY_PARAMS)?,
+        salt: Some(salt_str.as_salt()),
+        hash: Some(Output::new(hash)?),
+    })
+}
+
+/// Verifies against whichever of a PHC string or a legacy hash and salt is stored.
+/// On success, returns whether the stored hash should be replaced, which legacy ones always are.
+/// Accounts without a password, like single sign-on only ones, still take as long to fail as a wrong password.
+pub(crate) fn verify_stored(password: &str, phc: Option<&str>, legacy: Option<(&[u8], &[u8])>)
+    -> Result<bool, AuthError> {
+    match (phc, legacy) {
+        (Some(phc), _) => verify(password, phc),
+        (None, Some((hash, salt))) => verify_legacy(password, hash, salt).map(|()| true),
+        (None, None) => Err(verify_dummy(password))
     }
+}
 
-    pub fn as_password_hash<'a>(&'a self, salt_str: &'a SaltString) -> Result<PasswordHash<'a>, AuthError> {
-        Ok(PasswordHash {
-            algorithm: ALGO.into(),
-            version: Some(VER.into()),
-            params: ParamsString::try_from(A2.params())?,
-            salt: Some(salt_str.as_salt()),
-            hash: Some(Output::new(&self.hash[..])?),
-        })
+/// Does the same work as `verify` against a throwaway hash. Always fails.
+pub(crate) fn verify_dummy(password: &str) -> AuthError {
+    match verify(password, &DUMMY) {
+        Ok(_) => PASSWORD_ERROR,
+        Err(e) => e
     }
 }
 
-pub(crate) fn hash(password: &str) -> Result<Hashed, Error> {
-    let mut salt_bytes = [0u8; SALT_LEN];
-    rand_core::OsRng.fill_bytes(&mut salt_bytes);
-    let salt_str = SaltString::encode_b64(&salt_bytes)?;
-    let p = A2.hash_password(password.as_bytes(), salt_str.as_salt())?;
-    Ok(Hashed::new(
-        p.hash.expect("hash has output").as_bytes()
-            .try_into().expect("hash length is set"),
-        salt_bytes
-    ))
+const TOKEN_LEN: usize = 32;
+
+/// Creates a random single-use token, returned hex encoded.
+/// Only `token_digest` of it should be stored.
+pub(crate) fn new_token() -> String {
+    let mut bytes = [0u8; TOKEN_LEN];
+    rand_core::OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
 }
 
-pub(crate) fn verify(password: String, hash: Hashed) -> Result<(), AuthError> {
-    let salt_str = hash.get_salt_string()?;
-    A2.verify_password(password.as_bytes(), &hash.as_password_hash(&salt_str)?)
+pub(crate) fn token_digest(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
 }
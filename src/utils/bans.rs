@@ -0,0 +1,37 @@
+use sqlx::{MySql, Pool};
+use tide::Response;
+
+use crate::{models::Ban, utils::permissions::{self, Location}};
+
+/// The ban stopping the user from acting at `location`, if any.
+/// Only bans from everywhere apply at `Location::GLOBAL`, such as for profile changes.
+pub(crate) async fn active(pool: &Pool<MySql>, user_id: u32, location: Location) -> sqlx::Result<Option<Ban>> {
+    sqlx::query_as!(Ban,
+        "SELECT ban_id, forum_id, reason, created, expiry FROM bans
+         WHERE user_id = ? AND (expiry IS NULL OR expiry > NOW()) AND (forum_id IS NULL OR forum_id = ?)
+         ORDER BY expiry IS NULL DESC, expiry DESC LIMIT 1",
+        user_id, location.forum_id
+    ).fetch_optional(pool).await
+}
+
+/// A permanent ban from everywhere, which also keeps the user from logging in.
+pub(crate) async fn permanent(pool: &Pool<MySql>, user_id: u32) -> sqlx::Result<Option<Ban>> {
+    Ok(active(pool, user_id, Location::GLOBAL).await?.filter(|b| b.expiry.is_none()))
+}
+
+/// Every ban on the user that has not run out.
+pub(crate) async fn all_active(pool: &Pool<MySql>, user_id: u32) -> sqlx::Result<Vec<Ban>> {
+    sqlx::query_as!(Ban,
+        "SELECT ban_id, forum_id, reason, created, expiry FROM bans
+         WHERE user_id = ? AND (expiry IS NULL OR expiry > NOW()) ORDER BY ban_id",
+        user_id
+    ).fetch_all(pool).await
+}
+
+pub(crate) fn banned(ban: &Ban) -> Response {
+    let until = match ban.expiry {
+        Some(e) => format!(" until {} UTC", e),
+        None => String::new()
+    };
+    permissions::forbidden(&format!("banned{}: {}", until, ban.reason))
+}
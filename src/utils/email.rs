@@ -0,0 +1,86 @@
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use sha2::Sha256;
+use sqlx::{MySql, Pool};
+
+use crate::State;
+use crate::utils::env_or;
+
+/// What users without a verified email may do.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub(crate) enum UnverifiedPolicy {
+    Allow,
+    /// May reply in threads but not start them.
+    RepliesOnly,
+    Deny
+}
+
+lazy_static! {
+    static ref SECRET: Vec<u8> = hex::decode(
+        std::env::var("EMAIL_SECRET").expect("EMAIL_SECRET env var should be set")
+    ).expect("EMAIL_SECRET should contain valid hex");
+
+    static ref TOKEN_HOURS: i64 = env_or("EMAIL_TOKEN_HOURS", 48);
+
+    /// `UNVERIFIED_EMAIL_POLICY` is one of `allow` (the default), `replies_only` or `deny`.
+    static ref POLICY: UnverifiedPolicy = match std::env::var("UNVERIFIED_EMAIL_POLICY").as_deref() {
+        Ok("deny") => UnverifiedPolicy::Deny,
+        Ok("replies_only") => UnverifiedPolicy::RepliesOnly,
+        Ok("allow") | Err(_) => UnverifiedPolicy::Allow,
+        Ok(p) => panic!("UNVERIFIED_EMAIL_POLICY `{}` is not one of allow, replies_only, deny", p)
+    };
+}
+
+fn sign(payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&SECRET).expect("hmac accepts any key length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// A token proving `email` belongs to `user_id`, valid for `EMAIL_TOKEN_HOURS`.
+/// Signed rather than stored, so nothing needs cleaning up.
+pub(crate) fn verification_token(user_id: u32, email: &str) -> String {
+    let expiry = chrono::Utc::now().timestamp() + *TOKEN_HOURS * 60 * 60;
+    let payload = format!("{}:{}:{}", user_id, expiry, email);
+    format!(
+        "{}.{}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&payload),
+        hex::encode(sign(&payload).finalize().into_bytes())
+    )
+}
+
+/// Returns the user id and email a token is for, if it is genuine and unexpired.
+pub(crate) fn check_token(token: &str) -> Option<(u32, String)> {
+    let (payload, signature) = token.trim().split_once('.')?;
+    let payload = String::from_utf8(
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    sign(&payload).verify_slice(&hex::decode(signature).ok()?).ok()?;
+    let mut parts = payload.splitn(3, ':');
+    let user_id = parts.next()?.parse().ok()?;
+    let expiry: i64 = parts.next()?.parse().ok()?;
+    if expiry < chrono::Utc::now().timestamp() {
+        return None
+    }
+    Some((user_id, parts.next()?.to_string()))
+}
+
+pub(crate) async fn send_verification(state: &State, user_id: u32, email: &str) -> std::io::Result<()> {
+    state.mailer.send(
+        email,
+        "Verify your email",
+        &format!("Use this token to verify your email: {}\nIt expires in {} hours.",
+                 verification_token(user_id, email), *TOKEN_HOURS)
+    ).await
+}
+
+/// Whether the user may post under `UNVERIFIED_EMAIL_POLICY`.
+pub(crate) async fn may_post(pool: &Pool<MySql>, user_id: u32, new_thread: bool) -> sqlx::Result<bool> {
+    if *POLICY == UnverifiedPolicy::Allow {
+        return Ok(true)
+    }
+    let verified = sqlx::query!(
+        "SELECT email_verified `verified: bool` FROM users WHERE user_id = ?", user_id
+    ).fetch_one(pool).await?.verified;
+    Ok(verified || (*POLICY == UnverifiedPolicy::RepliesOnly && !new_thread))
+}
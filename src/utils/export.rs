@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use async_std::fs;
+use base64::Engine;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sqlx::{MySql, Pool};
+use tide::log;
+
+use crate::models::{ApiToken, Ban, LinkedIdentity, Log, SessionInfo, User};
+use crate::routes::images;
+use crate::utils::env_or;
+
+lazy_static! {
+    static ref EXPORT_DIR: PathBuf = PathBuf::from(
+        std::env::var("EXPORT_DIR").unwrap_or_else(|_| "./exports".to_string()));
+    static ref LINK_HOURS: u32 = env_or("EXPORT_LINK_HOURS", 24);
+    /// Exports pending for longer were lost to a restart, and count as failed.
+    pub(crate) static ref PENDING_MINUTES: u32 = env_or("EXPORT_PENDING_MINUTES", 60);
+}
+
+#[derive(Serialize)]
+struct ExportPost {
+    post_id: u32,
+    thread_id: u32,
+    thread_name: String,
+    post_pos: u32,
+    content: String,
+    time: chrono::NaiveDateTime
+}
+
+#[derive(Serialize)]
+struct ExportReaction {
+    post_id: u32,
+    reaction: String
+}
+
+#[derive(Serialize)]
+struct Export {
+    profile: User,
+    email: Option<String>,
+    posts: Vec<ExportPost>,
+    reactions: Vec<ExportReaction>,
+    audit_log: Vec<Log>,
+    sessions: Vec<SessionInfo>,
+    api_tokens: Vec<ApiToken>,
+    identities: Vec<LinkedIdentity>,
+    bans: Vec<Ban>,
+    // base64 of the image file
+    avatar: Option<String>
+}
+
+pub(crate) fn export_path(export_id: u32) -> PathBuf {
+    EXPORT_DIR.join(format!("{}.json", export_id))
+}
+
+async fn build(pool: &Pool<MySql>, export_id: u32, user_id: u32) -> tide::Result<()> {
+    let export = Export {
+        profile: sqlx::query_as!(User,
+            "SELECT user_id, username, description, profile_tag,
+             is_avatar_set AS `is_avatar_set: _`, is_admin AS `is_admin: _`
+             FROM users WHERE user_id = ?",
+            user_id
+        ).fetch_one(pool).await?,
+        email: sqlx::query!("SELECT email FROM users WHERE user_id = ?", user_id)
+            .fetch_one(pool).await?.email,
+        posts: sqlx::query_as!(ExportPost,
+            "SELECT post_id, thread_id, t.name thread_name, post_pos, content, time
+             FROM posts INNER JOIN threads t USING (thread_id)
+             WHERE user_id = ? ORDER BY post_id",
+            user_id
+        ).fetch_all(pool).await?,
+        reactions: sqlx::query_as!(ExportReaction,
+            "SELECT post_id, reaction FROM reactions_user WHERE reactor_id = ? ORDER BY post_id",
+            user_id
+        ).fetch_all(pool).await?,
+        audit_log: sqlx::query_as!(Log,
+            "SELECT log_id, log, time FROM audit_log WHERE user_id = ? ORDER BY log_id",
+            user_id
+        ).fetch_all(pool).await?,
+        sessions: sqlx::query_as!(SessionInfo,
+            "SELECT session_no, created, last_seen, ip, user_agent, FALSE AS `current: bool`
+             FROM sessions WHERE user_id = ? ORDER BY session_no",
+            user_id
+        ).fetch_all(pool).await?,
+        api_tokens: sqlx::query_as!(ApiToken,
+            "SELECT token_id, name, scope, created, expiry, last_used
+             FROM api_tokens WHERE user_id = ? ORDER BY token_id",
+            user_id
+        ).fetch_all(pool).await?,
+        identities: sqlx::query_as!(LinkedIdentity,
+            "SELECT identity_id, issuer, subject, email, created
+             FROM user_identities WHERE user_id = ? ORDER BY identity_id",
+            user_id
+        ).fetch_all(pool).await?,
+        bans: sqlx::query_as!(Ban,
+            "SELECT ban_id, forum_id, reason, created, expiry FROM bans WHERE user_id = ? ORDER BY ban_id",
+            user_id
+        ).fetch_all(pool).await?,
+        avatar: images::read_avatar(user_id).await?
+            .map(|a| base64::engine::general_purpose::STANDARD.encode(a))
+    };
+    fs::create_dir_all(&*EXPORT_DIR).await?;
+    fs::write(export_path(export_id), serde_json::to_vec(&export)?).await?;
+    Ok(())
+}
+
+/// Writes the export file and marks the export ready. Meant to be spawned.
+pub async fn run(pool: Pool<MySql>, export_id: u32, user_id: u32) {
+    let result = match build(&pool, export_id, user_id).await {
+        Ok(()) => sqlx::query!(
+            "UPDATE data_exports SET status = 'ready', expiry = NOW() + INTERVAL ? HOUR
+             WHERE export_id = ?",
+            *LINK_HOURS, export_id
+        ).execute(&pool).await,
+        Err(e) => {
+            log::error!("data export {} failed: {:?}", export_id, e);
+            // kept for a while so the user can see it failed
+            sqlx::query!(
+                "UPDATE data_exports SET status = 'failed', expiry = NOW() + INTERVAL ? HOUR WHERE export_id = ?",
+                *LINK_HOURS, export_id
+            ).execute(&pool).await
+        }
+    };
+    if let Err(e) = result {
+        log::error!("failed to update data export {}: {:?}", export_id, e);
+    }
+}
+
+/// Deletes expired export files and fails lost exports every `interval`. Never returns, so should be spawned.
+pub async fn sweep_expired(pool: Pool<MySql>, interval: Duration) {
+    loop {
+        if let Err(e) = sqlx::query!(
+            "UPDATE data_exports SET status = 'failed', expiry = NOW() + INTERVAL ? HOUR
+             WHERE status = 'pending' AND created <= NOW() - INTERVAL ? MINUTE",
+            *LINK_HOURS, *PENDING_MINUTES
+        ).execute(&pool).await {
+            log::error!("failed to fail lost data exports: {:?}", e);
+        }
+        match sqlx::query!("SELECT export_id FROM data_exports WHERE expiry <= NOW()")
+            .fetch_all(&pool).await {
+            Ok(expired) => for r in expired {
+                if let Err(e) = fs::remove_file(export_path(r.export_id)).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        log::error!("failed to delete data export {}: {:?}", r.export_id, e);
+                        continue
+                    }
+                }
+                if let Err(e) = sqlx::query!("DELETE FROM data_exports WHERE export_id = ?", r.export_id)
+                    .execute(&pool).await {
+                    log::error!("failed to delete data export {}: {:?}", r.export_id, e);
+                }
+            },
+            Err(e) => log::error!("failed to sweep data exports: {:?}", e)
+        }
+        async_std::task::sleep(interval).await;
+    }
+}
@@ -42,8 +42,9 @@ macro_rules! route_search {
             let query = req.query::<crate::utils::SearchQuery>()?;
 
             if let Some(q) = query.q {
-                let mut s = sqlx::query!($query, q).fetch(&req.state().db);
-                return Ok(serde_json::to_value(data_into_hashmap!(s))?.into());
+                // queries take the user first, to filter out what they cannot read
+                let mut s = sqlx::query!($query, $crate::utils::permissions::user_id(&req), q).fetch(&req.state().db);
+                return Ok(serde_json::to_value(data_into_vec!(s))?.into());
             }
             Ok(Response::builder(StatusCode::BadRequest)
                 .content_type("text/plain")
@@ -53,25 +54,19 @@ macro_rules! route_search {
     };
 }
 
-macro_rules! data_into_hashmap {
+// rows must come ordered by parent, then child, so that each parent's are together
+macro_rules! data_into_vec {
     ($s:ident) => {
         {
-            let mut data: HashMap::<u32, ContainerData> = HashMap::new();
+            let mut data: Vec<ContainerData<Container>> = Vec::new();
             while let Some(Ok(r)) = $s.next().await {
-                match data.entry(r.p_id) {
-                    Entry::Occupied(mut e) => {
-                        e.get_mut().children.push(Container {
-                            id: r.c_id, name: r.c_name, description: r.c_descr
-                        });
-                    },
-                    Entry::Vacant(e) => {
-                        e.insert(ContainerData {
-                            container: BasicContainer { name: r.p_name, description: r.p_descr },
-                            children: vec!(Container {
-                                id: r.c_id, name: r.c_name, description: r.c_descr
-                            })
-                        });
-                    }
+                let child = Container { id: r.c_id, name: r.c_name, description: r.c_descr };
+                match data.last_mut() {
+                    Some(d) if d.container.id == r.p_id => d.children.push(child),
+                    _ => data.push(ContainerData {
+                        container: Container { id: r.p_id, name: r.p_name, description: r.p_descr },
+                        children: vec!(child)
+                    })
                 }
             }
             data
@@ -99,5 +94,5 @@ pub(crate) use wrapper;
 pub(crate) use route_get;
 //pub(crate) use route_post;
 pub(crate) use route_search;
-pub(crate) use data_into_hashmap;
+pub(crate) use data_into_vec;
 pub(crate) use wrap_error;
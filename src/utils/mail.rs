@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use async_std::{fs::OpenOptions, io::WriteExt};
+use lettre::{AsyncSmtpTransport, AsyncStd1Executor, AsyncTransport, Message, message::Mailbox};
+use tide::log;
+
+
+/// Somewhere outgoing mail can be sent to.
+/// Chosen at startup by the `MAIL_SINK` env var, see `from_env`.
+#[tide::utils::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> std::io::Result<()>;
+}
+
+/// Prints mail to stdout. Only useful for local testing.
+pub struct StdoutMailer;
+
+#[tide::utils::async_trait]
+impl Mailer for StdoutMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> std::io::Result<()> {
+        println!("To: {}\nSubject: {}\n\n{}\n", to, subject, body);
+        Ok(())
+    }
+}
+
+/// Appends mail to a file. Only useful for local testing.
+pub struct FileMailer {
+    path: PathBuf
+}
+
+impl FileMailer {
+    pub fn new(path: impl Into<PathBuf>) -> FileMailer {
+        FileMailer {
+            path: path.into()
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(format!("To: {}\nSubject: {}\n\n{}\n\n", to, subject, body).as_bytes()).await
+    }
+}
+
+/// Sends mail through an SMTP server.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<AsyncStd1Executor>,
+    from: Mailbox
+}
+
+impl SmtpMailer {
+    /// `url` is in the form `smtp[s]://user:pass@host:port`, see `AsyncSmtpTransport::from_url`.
+    pub fn new(url: &str, from: Mailbox) -> SmtpMailer {
+        SmtpMailer {
+            transport: AsyncSmtpTransport::<AsyncStd1Executor>::from_url(url)
+                .expect("MAIL_SINK smtp url should be valid").build(),
+            from
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> std::io::Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse().map_err(std::io::Error::other)?)
+            .subject(subject)
+            .body(body.to_string())
+            .map_err(std::io::Error::other)?;
+        self.transport.send(message).await.map_err(std::io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// `MAIL_SINK` is `stdout` (the default), `file:<path>` or an `smtp://` or `smtps://` url.
+/// SMTP also needs `MAIL_FROM` to be set.
+pub fn from_env() -> Arc<dyn Mailer> {
+    match std::env::var("MAIL_SINK") {
+        Ok(s) if s.starts_with("smtp://") || s.starts_with("smtps://") => {
+            log::debug!("mail sink: smtp");
+            Arc::new(SmtpMailer::new(&s, std::env::var("MAIL_FROM")
+                .expect("MAIL_FROM env var should be set when using smtp")
+                .parse().expect("MAIL_FROM should be a valid mailbox")))
+        },
+        Ok(s) if s.starts_with("file:") => {
+            log::debug!("mail sink: file {}", &s[5..]);
+            Arc::new(FileMailer::new(&s[5..]))
+        },
+        _ => Arc::new(StdoutMailer)
+    }
+}
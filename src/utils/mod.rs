@@ -1,15 +1,33 @@
 pub(crate) mod auth;
+pub(crate) mod bans;
+pub(crate) mod email;
+pub(crate) mod export;
 pub(crate) mod macros;
+pub(crate) mod mail;
+pub(crate) mod oidc;
+pub(crate) mod permissions;
+pub(crate) mod pow;
 pub(crate) mod sessions;
+pub(crate) mod throttle;
+pub(crate) mod trash;
+pub(crate) mod totp;
+pub(crate) mod validation;
 
 use std::fmt::{Debug, Display, Formatter};
 pub(crate) use macros::wrapper;
 // pub(crate) use macros::wrapper_mut;
-pub(crate) use macros::{route_get, route_search, data_into_hashmap, SearchQuery};
+pub(crate) use macros::{route_get, route_search, data_into_vec, SearchQuery};
 // pub(crate) use macros::route_post;
 pub(crate) use macros::wrap_error;
 
 
+/// Reads an optional numeric env var, panicking if it is set but invalid.
+pub(crate) fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok()
+        .map(|v| v.parse().unwrap_or_else(|_| panic!("{} env var should be a number", name)))
+        .unwrap_or(default)
+}
+
 pub struct Error {
     pub(crate) msg: &'static str
 }
@@ -0,0 +1,220 @@
+use async_std::sync::RwLock;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{jwk::{JwkSet, KeyAlgorithm}, Algorithm, DecodingKey, Validation};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tide::StatusCode;
+
+use crate::utils::{auth, env_or};
+
+/// Set from `OIDC_ISSUER`, `OIDC_CLIENT_ID`, `OIDC_CLIENT_SECRET` and `OIDC_REDIRECT_URI`.
+/// Single sign-on is off without `OIDC_ISSUER`.
+/// Any issuer serving discovery works, including a local mock provider over plain http.
+pub(crate) struct Config {
+    pub issuer: String,
+    client_id: String,
+    // public clients rely on PKCE alone
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String
+}
+
+#[derive(Deserialize, Clone)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    #[serde(default = "default_signing_algs")]
+    id_token_signing_alg_values_supported: Vec<String>
+}
+
+// what providers must support when they do not say
+fn default_signing_algs() -> Vec<String> {
+    vec!["RS256".to_string()]
+}
+
+lazy_static! {
+    pub(crate) static ref CONFIG: Option<Config> = std::env::var("OIDC_ISSUER").ok().map(|issuer| Config {
+        issuer: issuer.trim_end_matches('/').to_string(),
+        client_id: std::env::var("OIDC_CLIENT_ID").expect("OIDC_CLIENT_ID env var should be set with OIDC_ISSUER"),
+        client_secret: std::env::var("OIDC_CLIENT_SECRET").ok(),
+        redirect_uri: std::env::var("OIDC_REDIRECT_URI")
+            .expect("OIDC_REDIRECT_URI env var should be set with OIDC_ISSUER"),
+        scopes: env_or("OIDC_SCOPES", "openid email".to_string())
+    });
+
+    static ref LOGIN_MINUTES: i64 = env_or("OIDC_LOGIN_MINUTES", 10);
+
+    // fetched on first use, keys again whenever a token names one not seen yet
+    static ref METADATA: RwLock<Option<Metadata>> = RwLock::new(None);
+    static ref JWKS: RwLock<Option<JwkSet>> = RwLock::new(None);
+}
+
+/// A login started with the provider, kept in the session until it comes back.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Pending {
+    pub state: String,
+    nonce: String,
+    verifier: String,
+    /// Set when linking an identity to this user rather than logging in.
+    pub link_user: Option<u32>,
+    pub remember: bool,
+    expiry: i64
+}
+
+impl Pending {
+    pub(crate) fn expired(&self) -> bool {
+        self.expiry < chrono::Utc::now().timestamp()
+    }
+}
+
+/// What is kept from a validated ID token.
+#[derive(Deserialize)]
+pub(crate) struct Claims {
+    pub sub: String,
+    nonce: Option<String>,
+    pub email: Option<String>
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String
+}
+
+fn provider_error(e: impl std::fmt::Display) -> tide::Error {
+    tide::Error::from_str(StatusCode::BadGateway, format!("identity provider: {}", e))
+}
+
+async fn metadata(config: &Config) -> tide::Result<Metadata> {
+    if let Some(m) = &*METADATA.read().await {
+        return Ok(m.clone())
+    }
+    let m: Metadata = surf::get(format!("{}/.well-known/openid-configuration", config.issuer))
+        .recv_json().await.map_err(provider_error)?;
+    if m.issuer.trim_end_matches('/') != config.issuer {
+        return Err(provider_error("discovery issuer does not match OIDC_ISSUER"))
+    }
+    *METADATA.write().await = Some(m.clone());
+    Ok(m)
+}
+
+/// The signature algorithm a key is pinned to, keys meant for encryption cannot verify an id token.
+fn signing_algorithm(alg: KeyAlgorithm) -> tide::Result<Algorithm> {
+    Ok(match alg {
+        KeyAlgorithm::HS256 => Algorithm::HS256,
+        KeyAlgorithm::HS384 => Algorithm::HS384,
+        KeyAlgorithm::HS512 => Algorithm::HS512,
+        KeyAlgorithm::ES256 => Algorithm::ES256,
+        KeyAlgorithm::ES384 => Algorithm::ES384,
+        KeyAlgorithm::RS256 => Algorithm::RS256,
+        KeyAlgorithm::RS384 => Algorithm::RS384,
+        KeyAlgorithm::RS512 => Algorithm::RS512,
+        KeyAlgorithm::PS256 => Algorithm::PS256,
+        KeyAlgorithm::PS384 => Algorithm::PS384,
+        KeyAlgorithm::PS512 => Algorithm::PS512,
+        KeyAlgorithm::EdDSA => Algorithm::EdDSA,
+        KeyAlgorithm::RSA1_5 | KeyAlgorithm::RSA_OAEP | KeyAlgorithm::RSA_OAEP_256 =>
+            return Err(provider_error(format!("id token key is for encryption ({:?}), not signing", alg)))
+    })
+}
+
+/// The key named `kid`, with the algorithm it is for if the provider pinned one.
+async fn decoding_key(metadata: &Metadata, kid: &str)
+    -> tide::Result<Option<(DecodingKey, Option<KeyAlgorithm>)>> {
+    if let Some(jwk) = JWKS.read().await.as_ref().and_then(|s| s.find(kid)) {
+        return Ok(Some((DecodingKey::from_jwk(jwk)?, jwk.common.key_algorithm)))
+    }
+    // keys may have been rotated since they were last fetched
+    let set: JwkSet = surf::get(&metadata.jwks_uri).recv_json().await.map_err(provider_error)?;
+    let key = match set.find(kid) {
+        Some(jwk) => Some((DecodingKey::from_jwk(jwk)?, jwk.common.key_algorithm)),
+        None => None
+    };
+    *JWKS.write().await = Some(set);
+    Ok(key)
+}
+
+/// Starts a login, giving the provider url to send the user to and what to keep for `finish`.
+pub(crate) async fn begin(config: &Config, link_user: Option<u32>, remember: bool)
+    -> tide::Result<(String, Pending)> {
+    let metadata = metadata(config).await?;
+    let pending = Pending {
+        state: auth::new_token(),
+        nonce: auth::new_token(),
+        verifier: auth::new_token(),
+        link_user,
+        remember,
+        expiry: chrono::Utc::now().timestamp() + *LOGIN_MINUTES * 60
+    };
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(pending.verifier.as_bytes()));
+    let mut url = tide::http::Url::parse(&metadata.authorization_endpoint)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_uri)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &pending.state)
+        .append_pair("nonce", &pending.nonce)
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256");
+    Ok((url.to_string(), pending))
+}
+
+/// Exchanges the code the provider sent back and validates the ID token it gives.
+/// `None` if the token is not valid for this login.
+pub(crate) async fn finish(config: &Config, pending: &Pending, code: &str) -> tide::Result<Option<Claims>> {
+    let metadata = metadata(config).await?;
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &config.redirect_uri),
+        ("client_id", &config.client_id),
+        ("code_verifier", &pending.verifier)
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret));
+    }
+    let mut res = surf::post(&metadata.token_endpoint)
+        .body(surf::Body::from_form(&form)?)
+        .await.map_err(provider_error)?;
+    if !res.status().is_success() {
+        // most likely a used or expired code, which is the user's to retry
+        tide::log::warn!("token endpoint refused code: {}", res.body_string().await.unwrap_or_default());
+        return Ok(None)
+    }
+    let tokens: TokenResponse = res.body_json().await.map_err(provider_error)?;
+
+    let header = match jsonwebtoken::decode_header(&tokens.id_token) {
+        Ok(h) => h,
+        Err(_) => return Ok(None)
+    };
+    let key = match header.kid {
+        Some(kid) => decoding_key(&metadata, &kid).await?,
+        None => None
+    };
+    let (key, key_alg) = match key {
+        Some(k) => k,
+        None => return Ok(None)
+    };
+    // the header is the token's own claim, so it only gets to pick from what the key or provider allows
+    let allowed: Vec<Algorithm> = match key_alg {
+        Some(a) => vec![signing_algorithm(a)?],
+        None => metadata.id_token_signing_alg_values_supported.iter().filter_map(|a| a.parse().ok()).collect()
+    };
+    if !allowed.contains(&header.alg) {
+        return Ok(None)
+    }
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    let claims = match jsonwebtoken::decode::<Claims>(&tokens.id_token, &key, &validation) {
+        Ok(t) => t.claims,
+        Err(_) => return Ok(None)
+    };
+    if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+        return Ok(None)
+    }
+    Ok(Some(claims))
+}
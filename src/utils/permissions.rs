@@ -0,0 +1,171 @@
+use sqlx::{MySql, Pool};
+use tide::{Response, StatusCode};
+
+use crate::{Request, models::{CurrentUser, Permission, Role}};
+
+/// Where in the category → forum → topic → thread hierarchy a permission is checked.
+/// Scoped roles count when held in the forum or the category containing it.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Location {
+    pub category_id: Option<u32>,
+    pub forum_id: Option<u32>
+}
+
+impl Location {
+    /// Outside any container, only unscoped roles count.
+    pub(crate) const GLOBAL: Location = Location { category_id: None, forum_id: None };
+
+    pub(crate) fn category(category_id: u32) -> Location {
+        Location { category_id: Some(category_id), forum_id: None }
+    }
+}
+
+/// Whether a user, or a guest for `None`, can see `location` at all, see `can_read_forum` in the migrations.
+pub(crate) async fn can_read(pool: &Pool<MySql>, user_id: Option<u32>, location: Location) -> sqlx::Result<bool> {
+    Ok(sqlx::query!(
+        "SELECT (? IS NULL OR can_read_category(?, ?)) AND (? IS NULL OR can_read_forum(?, ?)) `readable!: bool`",
+        location.category_id, user_id, location.category_id, location.forum_id, user_id, location.forum_id
+    ).fetch_one(pool).await?.readable)
+}
+
+/// Whether a user, or a guest for `None`, has `permission` at `location`.
+/// Nothing is allowed where the user cannot read.
+pub(crate) async fn has(pool: &Pool<MySql>, user_id: Option<u32>, permission: Permission, location: Location)
+    -> sqlx::Result<bool> {
+    if !can_read(pool, user_id, location).await? {
+        return Ok(false)
+    }
+    Ok(sqlx::query!(
+        "SELECT EXISTS(
+            SELECT * FROM role_permissions rp WHERE rp.permission = ? AND (
+                rp.role = 'guest'
+                OR (rp.role = 'member' AND ? IS NOT NULL)
+                OR rp.role IN (
+                    SELECT role FROM user_roles ur WHERE ur.user_id = ? AND (
+                        (ur.forum_id IS NULL AND ur.category_id IS NULL)
+                        OR ur.forum_id = ? OR ur.category_id = ?
+                    )
+                )
+            )
+         ) `allowed: bool`",
+        permission.as_str(), user_id, user_id, location.forum_id, location.category_id
+    ).fetch_one(pool).await?.allowed)
+}
+
+/// The user making the request, `None` for guests.
+pub(crate) fn current_user(req: &Request) -> Option<&CurrentUser> {
+    req.ext::<CurrentUser>()
+}
+
+pub(crate) fn user_id(req: &Request) -> Option<u32> {
+    current_user(req).map(|u| u.user_id)
+}
+
+/// `status` with a JSON body, for refusals `unauthorized` and `forbidden` do not fit.
+pub(crate) fn denied(status: StatusCode, error: &str) -> Response {
+    Response::builder(status).body(serde_json::json!({ "error": error })).build()
+}
+
+/// 401 with a JSON body, for when a login is needed.
+pub(crate) fn unauthorized() -> Response {
+    denied(StatusCode::Unauthorized, "login required")
+}
+
+/// 403 with a JSON body, for when the user is logged in but not allowed.
+pub(crate) fn forbidden(error: &str) -> Response {
+    denied(StatusCode::Forbidden, error)
+}
+
+/// The logged in user, otherwise the 401 to respond with.
+pub(crate) fn authenticated(req: &Request) -> Result<u32, Response> {
+    user_id(req).ok_or_else(unauthorized)
+}
+
+/// The user of a route behind `middleware::RequirePermission`, which already turned away everyone else.
+/// Should the route be mounted without it, guests get the 401 instead of a panic.
+pub(crate) fn guarded(req: &Request) -> tide::Result<Result<u32, Response>> {
+    Ok(authenticated(req))
+}
+
+/// The logged in user if they have `permission`,
+/// otherwise the 401 when not logged in or 403 when logged in to respond with.
+pub(crate) async fn require(req: &Request, permission: Permission, location: Location)
+    -> tide::Result<Result<u32, Response>> {
+    let user_id = match authenticated(req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(Err(resp))
+    };
+    if !has(&req.state().db, Some(user_id), permission, location).await? {
+        return Ok(Err(forbidden(&format!("missing permission `{}`", permission.as_str()))))
+    }
+    Ok(Ok(user_id))
+}
+
+/// The logged in user if they are the `:user_id` of the route.
+pub(crate) fn owner(req: &Request) -> tide::Result<Result<u32, Response>> {
+    let user_id = match authenticated(req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(Err(resp))
+    };
+    if req.param("user_id")?.parse::<u32>()? != user_id {
+        return Ok(Err(forbidden("only available to the user themselves")))
+    }
+    Ok(Ok(user_id))
+}
+
+/// `(acting user, target user)` if the logged in user is the `:user_id` of the route,
+/// or has `permission` outside any container.
+pub(crate) async fn owner_or(req: &Request, permission: Permission) -> tide::Result<Result<(u32, u32), Response>> {
+    let user_id = match authenticated(req) {
+        Ok(u) => u,
+        Err(resp) => return Ok(Err(resp))
+    };
+    let target_id = req.param("user_id")?.parse::<u32>()?;
+    if user_id != target_id && !has(&req.state().db, Some(user_id), permission, Location::GLOBAL).await? {
+        return Ok(Err(forbidden(&format!("missing permission `{}`", permission.as_str()))))
+    }
+    Ok(Ok((user_id, target_id)))
+}
+
+/// Whether the user holds `role` anywhere.
+pub(crate) async fn has_role(pool: &Pool<MySql>, user_id: u32, role: Role) -> sqlx::Result<bool> {
+    Ok(sqlx::query!(
+        "SELECT EXISTS(SELECT * FROM user_roles WHERE user_id = ? AND role = ?) `held: bool`",
+        user_id, role.as_str()
+    ).fetch_one(pool).await?.held)
+}
+
+// the locations below are `GLOBAL` for containers that do not exist or are deleted,
+// deleted forums and categories being unreadable instead
+
+pub(crate) async fn forum_location(pool: &Pool<MySql>, forum_id: u32) -> sqlx::Result<Location> {
+    Ok(sqlx::query!("SELECT category_id FROM forums WHERE forum_id = ?", forum_id)
+        .fetch_optional(pool).await?
+        .map_or(Location::GLOBAL, |r| Location { category_id: Some(r.category_id), forum_id: Some(forum_id) }))
+}
+
+pub(crate) async fn topic_location(pool: &Pool<MySql>, topic_id: u32) -> sqlx::Result<Location> {
+    Ok(sqlx::query!(
+        "SELECT category_id, forum_id FROM topics t INNER JOIN forums USING (forum_id)
+         WHERE topic_id = ? AND t.deleted IS NULL", topic_id
+    ).fetch_optional(pool).await?
+        .map_or(Location::GLOBAL, |r| Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) }))
+}
+
+pub(crate) async fn thread_location(pool: &Pool<MySql>, thread_id: u32) -> sqlx::Result<Location> {
+    Ok(sqlx::query!(
+        "SELECT category_id, forum_id FROM threads th
+         INNER JOIN topics t USING (topic_id) INNER JOIN forums USING (forum_id)
+         WHERE thread_id = ? AND th.deleted IS NULL AND t.deleted IS NULL", thread_id
+    ).fetch_optional(pool).await?
+        .map_or(Location::GLOBAL, |r| Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) }))
+}
+
+pub(crate) async fn post_location(pool: &Pool<MySql>, post_id: u32) -> sqlx::Result<Location> {
+    Ok(sqlx::query!(
+        "SELECT category_id, forum_id FROM posts p INNER JOIN threads th USING (thread_id)
+         INNER JOIN topics t USING (topic_id) INNER JOIN forums USING (forum_id)
+         WHERE post_id = ? AND p.deleted IS NULL AND th.deleted IS NULL AND t.deleted IS NULL", post_id
+    ).fetch_optional(pool).await?
+        .map_or(Location::GLOBAL, |r| Location { category_id: Some(r.category_id), forum_id: Some(r.forum_id) }))
+}
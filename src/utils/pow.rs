@@ -0,0 +1,151 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{MySql, Pool};
+use tide::sessions::Session;
+
+use crate::utils::{auth, env_or};
+
+const CHALLENGE_KEY: &str = "pow_challenge";
+
+lazy_static! {
+    /// Leading zero bits a solution's hash needs, each one doubles the expected work.
+    static ref BASE_DIFFICULTY: u32 = env_or("POW_DIFFICULTY", 18);
+    static ref MAX_DIFFICULTY: u32 = env_or("POW_MAX_DIFFICULTY", 28);
+    /// Signups an IP gets at the base difficulty within the window, each one after adds a bit.
+    static ref IP_FREE_SIGNUPS: u32 = env_or("POW_IP_FREE_SIGNUPS", 3);
+    static ref IP_WINDOW_HOURS: u32 = env_or("POW_IP_WINDOW_HOURS", 24);
+    static ref CHALLENGE_MINUTES: i64 = env_or("POW_CHALLENGE_MINUTES", 10);
+}
+
+/// A hashcash style challenge: find a `nonce` where SHA-256 of `"<challenge>:<nonce>"`
+/// starts with `difficulty` zero bits.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Challenge {
+    pub challenge: String,
+    pub difficulty: u32,
+    expiry: i64
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break
+        }
+    }
+    bits
+}
+
+/// Difficulty for the next signup from `ip`, raised by how many it made recently.
+async fn difficulty(pool: &Pool<MySql>, ip: &str) -> sqlx::Result<u32> {
+    let recent = sqlx::query!(
+        "SELECT COUNT(*) AS `count: u32` FROM signups WHERE ip = ? AND created > NOW() - INTERVAL ? HOUR",
+        ip, *IP_WINDOW_HOURS
+    ).fetch_one(pool).await?.count;
+    Ok((*BASE_DIFFICULTY + recent.saturating_sub(*IP_FREE_SIGNUPS)).min(*MAX_DIFFICULTY))
+}
+
+/// Issues a new challenge into the session, replacing any unsolved one.
+pub(crate) async fn issue(pool: &Pool<MySql>, sess: &mut Session, ip: &str) -> tide::Result<Challenge> {
+    let challenge = Challenge {
+        challenge: auth::new_token(),
+        difficulty: difficulty(pool, ip).await?,
+        expiry: chrono::Utc::now().timestamp() + *CHALLENGE_MINUTES * 60
+    };
+    sess.insert(CHALLENGE_KEY, &challenge)?;
+    Ok(challenge)
+}
+
+/// Checks a nonce against the session's challenge.
+/// A wrong nonce uses the challenge up, so each one only gets one try.
+/// A right one is kept until `consume`, so a registration failing validation can be retried with it.
+pub(crate) fn check(sess: &mut Session, nonce: &str) -> bool {
+    let challenge = match sess.get::<Challenge>(CHALLENGE_KEY) {
+        Some(c) => c,
+        None => return false
+    };
+    if challenge.expiry < chrono::Utc::now().timestamp() || !solves(&challenge, nonce) {
+        sess.remove(CHALLENGE_KEY);
+        return false
+    }
+    true
+}
+
+fn solves(challenge: &Challenge, nonce: &str) -> bool {
+    let hash = Sha256::digest(format!("{}:{}", challenge.challenge, nonce).as_bytes());
+    leading_zero_bits(&hash) >= challenge.difficulty
+}
+
+/// Uses up the solved challenge, once the registration it was for is going ahead.
+pub(crate) fn consume(sess: &mut Session) {
+    sess.remove(CHALLENGE_KEY);
+}
+
+/// Counts a signup against `ip`, forgetting ones that no longer affect difficulty.
+pub(crate) async fn record_signup(pool: &Pool<MySql>, ip: &str) -> sqlx::Result<()> {
+    sqlx::query!("DELETE FROM signups WHERE created < NOW() - INTERVAL ? HOUR", *IP_WINDOW_HOURS)
+        .execute(pool).await?;
+    sqlx::query!("INSERT INTO signups(ip, created) VALUES (?, NOW())", ip)
+        .execute(pool).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(difficulty: u32, expiry: i64) -> Challenge {
+        Challenge { challenge: "abc".to_string(), difficulty, expiry }
+    }
+
+    fn session_with(challenge: &Challenge) -> Session {
+        let mut sess = Session::new();
+        sess.insert(CHALLENGE_KEY, challenge).expect("challenge serializes");
+        sess
+    }
+
+    fn in_a_minute() -> i64 {
+        chrono::Utc::now().timestamp() + 60
+    }
+
+    /// The first nonce that does or does not solve `challenge`, found the way a client would.
+    fn nonce(challenge: &Challenge, solved: bool) -> String {
+        (0u64..).map(|n| n.to_string()).find(|n| solves(challenge, n) == solved).expect("some nonce fits")
+    }
+
+    #[test]
+    fn leading_zero_bits_counts_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x01, 0xff]), 7);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00, 0x10]), 19);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn a_solved_challenge_is_kept_until_consumed() {
+        let c = challenge(8, in_a_minute());
+        let mut sess = session_with(&c);
+        let right = nonce(&c, true);
+        assert!(check(&mut sess, &right));
+        assert!(check(&mut sess, &right));
+        consume(&mut sess);
+        assert!(!check(&mut sess, &right));
+    }
+
+    #[test]
+    fn a_wrong_nonce_uses_the_challenge_up() {
+        let c = challenge(8, in_a_minute());
+        let mut sess = session_with(&c);
+        assert!(!check(&mut sess, &nonce(&c, false)));
+        assert!(!check(&mut sess, &nonce(&c, true)));
+    }
+
+    #[test]
+    fn expired_or_missing_challenges_fail() {
+        let mut sess = session_with(&challenge(0, chrono::Utc::now().timestamp() - 1));
+        assert!(!check(&mut sess, "0"));
+        assert!(!check(&mut Session::new(), "0"));
+    }
+}
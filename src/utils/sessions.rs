@@ -1,6 +1,23 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
 use sqlx::{MySql, Pool};
 use tide::{log, sessions::{Session, SessionStore}};
 
+use crate::utils::env_or;
+
+/// Session key for "remember me", which picks the long lifetime.
+pub(crate) const REMEMBER_KEY: &str = "remember";
+
+pub(crate) const COOKIE_NAME: &str = "10_c";
+
+lazy_static! {
+    static ref SHORT_LIFETIME: Duration = Duration::from_secs(env_or("SESSION_SHORT_MINUTES", 120) * 60);
+    pub(crate) static ref LONG_LIFETIME: Duration = Duration::from_secs(env_or("SESSION_LONG_DAYS", 30) * 24 * 60 * 60);
+    /// Sessions never live longer than this from creation, however active they are.
+    static ref MAX_LIFETIME: Duration = Duration::from_secs(env_or("SESSION_MAX_DAYS", 90) * 24 * 60 * 60);
+}
+
 
 /// A workaround for tide issue #762.
 /// https://github.com/http-rs/tide/issues/762
@@ -38,6 +55,49 @@ impl SessionWorkaroundExt for Session {
     }
 }
 
+/// Pushes back the expiry of an active session, up to its maximum lifetime.
+/// Stores only happen when session data changes, so this is what makes expiry sliding.
+/// Returns when the cookie should now expire if it was extended, `Some(None)` meaning with the browser session.
+pub async fn extend_session(pool: &Pool<MySql>, sess_id: &str) -> sqlx::Result<Option<Option<SystemTime>>> {
+    let extended = sqlx::query!(
+        "UPDATE sessions SET last_seen = NOW(), expiry = LEAST(
+            NOW() + INTERVAL IF(remember, ?, ?) SECOND, created + INTERVAL ? SECOND
+         ) WHERE sess_id = ? AND (last_seen IS NULL OR last_seen < NOW() - INTERVAL 1 MINUTE)",
+        LONG_LIFETIME.as_secs(), SHORT_LIFETIME.as_secs(), MAX_LIFETIME.as_secs(), sess_id
+    ).execute(pool).await?.rows_affected() != 0;
+    if !extended {
+        return Ok(None)
+    }
+    Ok(sqlx::query!(
+        "SELECT expiry `expiry!: NaiveDateTime`, remember `remember: bool` FROM sessions WHERE sess_id = ?", sess_id
+    ).fetch_optional(pool).await?.map(|r| r.remember.then(||
+        UNIX_EPOCH + Duration::from_secs(r.expiry.timestamp().max(0) as u64)
+    )))
+}
+
+/// Deletes expired sessions every `interval`. Never returns, so should be spawned.
+pub async fn sweep_expired(pool: Pool<MySql>, interval: Duration) {
+    loop {
+        match sqlx::query!(
+            "DELETE FROM sessions WHERE expiry <= NOW() OR created <= NOW() - INTERVAL ? SECOND",
+            MAX_LIFETIME.as_secs()
+        ).execute(&pool).await {
+            Ok(r) => log::debug!("swept {} expired sessions", r.rows_affected()),
+            Err(e) => log::error!("failed to sweep expired sessions: {:?}", e)
+        }
+        async_std::task::sleep(interval).await;
+    }
+}
+
+/// Destroys every session belonging to `user_id`, except the one with id `keep` if given.
+pub async fn clear_user_sessions(pool: &Pool<MySql>, user_id: u32, keep: Option<&str>) -> sqlx::Result<()> {
+    sqlx::query!(
+        "DELETE FROM sessions WHERE user_id = ? AND sess_id <> COALESCE(?, '')",
+        user_id, keep
+    ).execute(pool).await?;
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct MysqlSessionStore {
     pool: Pool<MySql>
@@ -56,13 +116,17 @@ impl SessionStore for MysqlSessionStore {
     async fn load_session(&self, cookie_value: String) -> async_session::Result<Option<Session>> {
         let id = Session::id_from_cookie_value(&cookie_value)?;
         let rec = sqlx::query!(
-            "SELECT data FROM sessions WHERE sess_id = ? AND (expiry IS NULL OR expiry > NOW())",
+            "SELECT data, expiry FROM sessions WHERE sess_id = ? AND (expiry IS NULL OR expiry > NOW())",
             id
         ).fetch_optional(&self.pool).await?;
 
         match rec {
             Some(r) => {
-                let sess: Option<Session> = serde_json::from_str(&r.data)?;
+                let mut sess: Option<Session> = serde_json::from_str(&r.data)?;
+                // the column is extended by `extend_session` without touching the data
+                if let (Some(s), Some(expiry)) = (&mut sess, r.expiry) {
+                    s.set_expiry(Utc.from_utc_datetime(&expiry));
+                }
                 match &sess {
                     Some(s) => log::debug!("loaded session: {:?} | data_changed: {:?}", s, s.data_changed()),
                     None => log::warn!("failed to load session with id {}", id)
@@ -78,12 +142,23 @@ impl SessionStore for MysqlSessionStore {
 
     async fn store_session(&self, mut session: Session) -> async_session::Result<Option<String>> {
         if session.should_regenerate() {
+            let old_id = session.id().to_string();
             session.regenerate();
+            sqlx::query!("DELETE FROM sessions WHERE sess_id = ?", old_id)
+                .execute(&self.pool).await?;
         }
+        let remember = session.get::<bool>(REMEMBER_KEY).unwrap_or(false);
+        session.expire_in(if remember { *LONG_LIFETIME } else { *SHORT_LIFETIME });
+        // `ip` and `user_agent` are kept up to date by `SessionTrackMiddleware`
         return match sqlx::query!(
-            "INSERT INTO sessions(sess_id, expiry, data) VALUES (?, ?, ?) AS new
-             ON DUPLICATE KEY UPDATE expiry = NEW.expiry, data = NEW.data",
-            session.id(), session.expiry(), serde_json::to_string(&session)?
+            "INSERT INTO sessions(sess_id, expiry, data, user_id, ip, user_agent, last_seen, remember)
+             VALUES (?, ?, ?, ?, ?, ?, NOW(), ?) AS new
+             ON DUPLICATE KEY UPDATE expiry = LEAST(NEW.expiry, sessions.created + INTERVAL ? SECOND),
+             data = NEW.data, user_id = NEW.user_id, ip = NEW.ip, user_agent = NEW.user_agent,
+             last_seen = NEW.last_seen, remember = NEW.remember",
+            session.id(), session.expiry(), serde_json::to_string(&session)?,
+            session.get::<u32>("user_id"), session.get::<String>("ip"), session.get::<String>("user_agent"),
+            remember, MAX_LIFETIME.as_secs()
         ).execute(&self.pool).await {
             Ok(_) => Ok(session.into_cookie_value()),
             Err(e) => Err(e.into())
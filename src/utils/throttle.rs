@@ -0,0 +1,146 @@
+use std::borrow::Cow;
+use sha2::{Digest, Sha256};
+use sqlx::{MySql, Pool};
+
+use crate::Request;
+
+// failures older than this are forgotten
+const FORGET_AFTER_HOURS: u32 = 24;
+const BASE_LOCKOUT_SECS: u32 = 30;
+const MAX_LOCKOUT_SECS: u32 = 60 * 60;
+// the size of `login_failures.key_value`
+const KEY_LEN: usize = 128;
+
+/// What failed login attempts are counted against.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Kind {
+    /// Keyed by the submitted username, whether or not it exists,
+    /// so a lockout does not reveal which usernames are real.
+    Account,
+    Ip
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Account => "account",
+            Kind::Ip => "ip"
+        }
+    }
+
+    /// Failures allowed before lockouts start.
+    /// Higher for IPs since many users can share one.
+    fn free_attempts(&self) -> u32 {
+        match self {
+            Kind::Account => 5,
+            Kind::Ip => 20
+        }
+    }
+}
+
+/// The address of the connecting socket, without the port.
+/// Forwarding headers are ignored since they can be set by the client.
+pub(crate) fn client_ip(req: &Request) -> String {
+    match req.peer_addr() {
+        Some(addr) => match addr.parse::<std::net::SocketAddr>() {
+            Ok(a) => a.ip().to_string(),
+            Err(_) => addr.to_string()
+        },
+        None => "unknown".to_string()
+    }
+}
+
+/// The key as stored. Submitted usernames can be any length, so longer ones are hashed to fit.
+fn stored_key(key: &str) -> Cow<'_, str> {
+    if key.len() <= KEY_LEN {
+        Cow::Borrowed(key)
+    } else {
+        Cow::Owned(hex::encode(Sha256::digest(key.as_bytes())))
+    }
+}
+
+pub(crate) async fn is_locked(pool: &Pool<MySql>, username: &str, ip: &str) -> sqlx::Result<bool> {
+    Ok(sqlx::query!(
+        "SELECT EXISTS(
+            SELECT * FROM login_failures WHERE locked_until > NOW()
+            AND ((kind = ? AND key_value = ?) OR (kind = ? AND key_value = ?))
+         ) `locked: bool`",
+        Kind::Account.as_str(), &*stored_key(username), Kind::Ip.as_str(), &*stored_key(ip)
+    ).fetch_one(pool).await?.locked)
+}
+
+/// Counts a failure, locking the key with exponential backoff once past its free attempts.
+/// Returns whether the key is now locked.
+pub(crate) async fn record_failure(pool: &Pool<MySql>, kind: Kind, key: &str) -> sqlx::Result<bool> {
+    let stored = stored_key(key);
+    let key: &str = &stored;
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO login_failures(kind, key_value, failures, last_failure) VALUES (?, ?, 1, NOW()) AS new
+         ON DUPLICATE KEY UPDATE failures = IF(
+            login_failures.last_failure < NOW() - INTERVAL ? HOUR, 1, login_failures.failures + 1
+         ), last_failure = NEW.last_failure",
+        kind.as_str(), key, FORGET_AFTER_HOURS
+    ).execute(&mut tx).await?;
+    let failures = sqlx::query!(
+        "SELECT failures FROM login_failures WHERE kind = ? AND key_value = ? FOR UPDATE",
+        kind.as_str(), key
+    ).fetch_one(&mut tx).await?.failures;
+
+    let locked = failures >= kind.free_attempts();
+    if locked {
+        let doublings = (failures - kind.free_attempts()).min(16);
+        let lockout = BASE_LOCKOUT_SECS.saturating_mul(1 << doublings).min(MAX_LOCKOUT_SECS);
+        sqlx::query!(
+            "UPDATE login_failures SET locked_until = NOW() + INTERVAL ? SECOND
+             WHERE kind = ? AND key_value = ?",
+            lockout, kind.as_str(), key
+        ).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(locked)
+}
+
+pub(crate) async fn clear(pool: &Pool<MySql>, kind: Kind, key: &str) -> sqlx::Result<()> {
+    sqlx::query!("DELETE FROM login_failures WHERE kind = ? AND key_value = ?", kind.as_str(), &*stored_key(key))
+        .execute(pool).await?;
+    Ok(())
+}
+
+/// Records a failed login for both the account and the IP, writing it to `audit_log`.
+pub(crate) async fn login_failed(pool: &Pool<MySql>, username: &str, ip: &str, step: &str) -> sqlx::Result<()> {
+    sqlx::query!(
+        "INSERT INTO audit_log(user_id, log) VALUES (NULL, ?)",
+        format!("Failed login ({}) for `{}` from {}", step, username, ip)
+    ).execute(pool).await?;
+    for (kind, key) in [(Kind::Account, username), (Kind::Ip, ip)] {
+        if record_failure(pool, kind, key).await? {
+            sqlx::query!(
+                "INSERT INTO audit_log(user_id, log) VALUES (NULL, ?)",
+                format!("Locked out {} `{}` after failed login from {}", kind.as_str(), key, ip)
+            ).execute(pool).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_key_keeps_short_keys() {
+        assert_eq!(stored_key("bob"), "bob");
+        assert_eq!(stored_key("203.0.113.7"), "203.0.113.7");
+    }
+
+    #[test]
+    fn stored_key_fits_long_keys_in_the_column() {
+        let long = "x".repeat(KEY_LEN + 1);
+        assert!(stored_key(&long).len() <= KEY_LEN);
+        assert_eq!(stored_key(&long), stored_key(&long));
+        assert_ne!(stored_key(&long), stored_key(&"y".repeat(KEY_LEN + 1)));
+        // the column limit is hit by bytes first
+        assert!(stored_key(&"é".repeat(KEY_LEN)).len() <= KEY_LEN);
+    }
+}
@@ -0,0 +1,109 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tide::http::Url;
+
+// RFC 6238 defaults, which is what authenticator apps expect
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LEN: usize = 20;
+// how many steps either side of now are accepted, for clock drift
+const WINDOW: u64 = 1;
+const ISSUER: &str = "CS6131Forum";
+
+pub(crate) fn new_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+pub(crate) fn encode_secret(secret: &[u8]) -> String {
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+/// The `otpauth://` URI authenticator apps read, usually shown as a QR code.
+pub(crate) fn provisioning_uri(username: &str, secret: &[u8]) -> String {
+    let mut uri = Url::parse("otpauth://totp").expect("otpauth uri is valid");
+    uri.path_segments_mut().expect("otpauth uri has a host")
+        .push(&format!("{}:{}", ISSUER, username));
+    uri.query_pairs_mut()
+        .append_pair("secret", &encode_secret(secret))
+        .append_pair("issuer", ISSUER)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP.to_string());
+    uri.into()
+}
+
+fn current_step() -> u64 {
+    chrono::Utc::now().timestamp() as u64 / STEP
+}
+
+fn code_at(secret: &[u8], step: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    // dynamic truncation, RFC 4226 section 5.3
+    let offset = (digest[digest.len() - 1] & 0xf) as usize;
+    let bin = u32::from_be_bytes(digest[offset..offset + 4].try_into().expect("slice is 4 bytes"))
+        & 0x7fff_ffff;
+    bin % 10u32.pow(DIGITS)
+}
+
+/// Checks `code` against the steps around now.
+/// Returns the matching step, which should be stored so the code cannot be replayed.
+pub(crate) fn verify(secret: &[u8], code: &str, last_step: Option<u64>) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None
+    }
+    let code: u32 = code.parse().ok()?;
+    let now = current_step();
+    (now.saturating_sub(WINDOW)..=now + WINDOW)
+        .filter(|s| last_step.map_or(true, |l| *s > l))
+        .find(|s| code_at(secret, *s) == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA1, cut to 6 digits
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn code_at_matches_rfc_vectors() {
+        assert_eq!(code_at(RFC_SECRET, 59 / STEP), 287082);
+        assert_eq!(code_at(RFC_SECRET, 1111111109 / STEP), 81804);
+        assert_eq!(code_at(RFC_SECRET, 1234567890 / STEP), 5924);
+        assert_eq!(code_at(RFC_SECRET, 2000000000 / STEP), 279037);
+    }
+
+    #[test]
+    fn verify_accepts_codes_within_window() {
+        let secret = new_secret();
+        let now = current_step();
+        let code = format!("{:06}", code_at(&secret, now));
+        assert!(verify(&secret, &code, None).is_some());
+        assert!(verify(&secret, &format!(" {} ", code), None).is_some());
+        let old = format!("{:06}", code_at(&secret, now - 5));
+        assert_eq!(verify(&secret, &old, None), None);
+    }
+
+    #[test]
+    fn verify_refuses_replays() {
+        let secret = new_secret();
+        let now = current_step();
+        let code = format!("{:06}", code_at(&secret, now));
+        let step = verify(&secret, &code, None).expect("fresh code verifies");
+        assert_eq!(verify(&secret, &code, Some(step)), None);
+    }
+
+    #[test]
+    fn verify_refuses_malformed_codes() {
+        let secret = new_secret();
+        assert_eq!(verify(&secret, "12345", None), None);
+        assert_eq!(verify(&secret, "1234567", None), None);
+        assert_eq!(verify(&secret, "abcdef", None), None);
+    }
+}
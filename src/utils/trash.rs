@@ -0,0 +1,67 @@
+use std::time::Duration;
+use lazy_static::lazy_static;
+use sqlx::{MySql, Pool};
+use tide::log;
+
+use crate::utils::env_or;
+
+lazy_static! {
+    /// How long deleted content can be restored for, after which it is purged.
+    pub(crate) static ref RETENTION_DAYS: u32 = env_or("TRASH_RETENTION_DAYS", 30);
+}
+
+/// When something deleted at `deleted` gets purged.
+pub(crate) fn purge_after(deleted: chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+    deleted + chrono::Duration::days(*RETENTION_DAYS as i64)
+}
+
+/// Deletes a post for good, moving every later post in its thread up a position.
+async fn purge_post(pool: &Pool<MySql>, post_id: u32) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    // positions are renumbered here since a trigger on posts may not update posts itself,
+    // and last_pos is cleared meanwhile so it never names a position that is being shifted
+    let info = match sqlx::query!(
+        "SELECT thread_id, post_pos, last_pos FROM posts INNER JOIN threads USING (thread_id) WHERE post_id = ?",
+        post_id
+    ).fetch_optional(&mut tx).await? {
+        Some(i) => i,
+        None => return Ok(())
+    };
+    sqlx::query!("UPDATE threads SET last_pos = NULL WHERE thread_id = ?", info.thread_id)
+        .execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM posts WHERE post_id = ?", post_id)
+        .execute(&mut tx).await?;
+    sqlx::query!(
+        "UPDATE posts SET post_pos = post_pos - 1 WHERE thread_id = ? AND post_pos > ?",
+        info.thread_id, info.post_pos
+    ).execute(&mut tx).await?;
+    sqlx::query!("UPDATE threads SET last_pos = ? - 1 WHERE thread_id = ?", info.last_pos, info.thread_id)
+        .execute(&mut tx).await?;
+    tx.commit().await
+}
+
+/// Deletes everything deleted longer ago than the retention window.
+async fn purge_expired(pool: &Pool<MySql>) -> sqlx::Result<()> {
+    let days = *RETENTION_DAYS;
+    // from the bottom up, as deleting a container takes everything in it
+    sqlx::query!("DELETE FROM threads WHERE deleted <= NOW() - INTERVAL ? DAY", days).execute(pool).await?;
+    sqlx::query!("DELETE FROM topics WHERE deleted <= NOW() - INTERVAL ? DAY", days).execute(pool).await?;
+    sqlx::query!("DELETE FROM forums WHERE deleted <= NOW() - INTERVAL ? DAY", days).execute(pool).await?;
+    sqlx::query!("DELETE FROM categories WHERE deleted <= NOW() - INTERVAL ? DAY", days).execute(pool).await?;
+    let posts = sqlx::query!("SELECT post_id FROM posts WHERE deleted <= NOW() - INTERVAL ? DAY", days)
+        .fetch_all(pool).await?;
+    for r in posts {
+        purge_post(pool, r.post_id).await?;
+    }
+    Ok(())
+}
+
+/// Purges expired deleted content every `interval`. Never returns, so should be spawned.
+pub async fn sweep_expired(pool: Pool<MySql>, interval: Duration) {
+    loop {
+        if let Err(e) = purge_expired(&pool).await {
+            log::error!("failed to purge deleted content: {:?}", e);
+        }
+        async_std::task::sleep(interval).await;
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sqlx::{MySql, Pool};
+use tide::{log, Response, StatusCode};
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::GeneralSecurityProfile;
+
+use crate::utils::env_or;
+
+const DEFAULT_RESERVED: &[&str] = &[
+    "admin", "administrator", "moderator", "mod", "root", "system", "support", "staff",
+    "deleted", "anonymous", "guest", "null", "undefined"
+];
+// allowed in usernames on top of letters and digits
+const USERNAME_PUNCTUATION: &[char] = &['_', '-', '.'];
+
+/// Registration rules, configured by env vars.
+struct Policy {
+    username_min: usize,
+    username_max: usize,
+    password_min: usize,
+    password_max: usize,
+    /// Skeletons of reserved names, so lookalikes are reserved too.
+    reserved: HashSet<String>,
+    /// Lowercased passwords from `BREACHED_PASSWORDS_FILE`, one per line.
+    breached: HashSet<String>
+}
+
+lazy_static! {
+    static ref POLICY: Policy = Policy {
+        username_min: env_or("USERNAME_MIN_LEN", 3),
+        username_max: env_or("USERNAME_MAX_LEN", 32),
+        password_min: env_or("PASSWORD_MIN_LEN", 8),
+        password_max: env_or("PASSWORD_MAX_LEN", 1024),
+        reserved: DEFAULT_RESERVED.iter().map(|s| s.to_string())
+            .chain(std::env::var("RESERVED_USERNAMES").unwrap_or_default()
+                .split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()))
+            .map(|s| username_skeleton(&normalize_username(&s)))
+            .collect(),
+        breached: match std::env::var("BREACHED_PASSWORDS_FILE") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("BREACHED_PASSWORDS_FILE {} cannot be read: {}", path, e))
+                .lines().map(|l| l.trim().to_lowercase()).filter(|l| !l.is_empty())
+                .collect(),
+            Err(_) => HashSet::new()
+        }
+    };
+}
+
+/// One broken rule, sent back to the client as part of a 422.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Violation {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String
+}
+
+impl Violation {
+    pub fn new(field: &'static str, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            code,
+            message: message.into()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Violations {
+    errors: Vec<Violation>
+}
+
+pub(crate) fn unprocessable(errors: Vec<Violation>) -> tide::Result {
+    Ok(Response::builder(StatusCode::UnprocessableEntity)
+        .body(serde_json::to_value(Violations { errors })?)
+        .build())
+}
+
+/// The form usernames are stored and looked up in.
+pub(crate) fn normalize_username(username: &str) -> String {
+    username.trim().nfkc().collect()
+}
+
+/// Usernames with the same skeleton look alike, so only one of them may exist.
+/// UTS #39 skeleton of the lowercased name.
+pub(crate) fn username_skeleton(normalized: &str) -> String {
+    unicode_security::skeleton(&normalized.to_lowercase()).collect()
+}
+
+pub(crate) fn check_username(normalized: &str) -> Vec<Violation> {
+    let mut errors = vec![];
+    let len = normalized.chars().count();
+    if len < POLICY.username_min {
+        errors.push(Violation::new("username", "too_short",
+            format!("must be at least {} characters", POLICY.username_min)));
+    }
+    if len > POLICY.username_max {
+        errors.push(Violation::new("username", "too_long",
+            format!("must be at most {} characters", POLICY.username_max)));
+    }
+    if !normalized.chars().all(|c| (c.is_alphanumeric() && c.identifier_allowed())
+        || USERNAME_PUNCTUATION.contains(&c)) {
+        errors.push(Violation::new("username", "invalid_characters",
+            "may only contain letters, digits, `_`, `-` and `.`"));
+    }
+    if POLICY.reserved.contains(&username_skeleton(normalized)) {
+        errors.push(Violation::new("username", "reserved", "is reserved"));
+    }
+    errors
+}
+
+pub(crate) fn check_password(password: &str, username: &str) -> Vec<Violation> {
+    let mut errors = vec![];
+    let len = password.chars().count();
+    if len < POLICY.password_min {
+        errors.push(Violation::new("password", "too_short",
+            format!("must be at least {} characters", POLICY.password_min)));
+    }
+    if len > POLICY.password_max {
+        errors.push(Violation::new("password", "too_long",
+            format!("must be at most {} characters", POLICY.password_max)));
+    }
+    let lower = password.to_lowercase();
+    if !username.is_empty() && lower.contains(&username.to_lowercase()) {
+        errors.push(Violation::new("password", "contains_username", "must not contain the username"));
+    }
+    if password.chars().collect::<HashSet<_>>().len() < 3 {
+        errors.push(Violation::new("password", "too_simple", "must use at least 3 different characters"));
+    }
+    if POLICY.breached.contains(&lower) {
+        errors.push(Violation::new("password", "breached", "appears in a list of breached passwords"));
+    }
+    errors
+}
+
+const EMAIL_MAX_LEN: usize = 254;
+
+/// Only catches obvious mistakes, verification is the real check.
+pub(crate) fn check_email(email: &str) -> Vec<Violation> {
+    let valid = match email.rsplit_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.')
+            && !domain.starts_with('.') && !domain.ends_with('.')
+            && !email.chars().any(|c| c.is_whitespace() || c.is_control()),
+        None => false
+    };
+    if !valid || email.len() > EMAIL_MAX_LEN {
+        return vec![Violation::new("email", "invalid", "is not a valid email address")]
+    }
+    vec![]
+}
+
+/// Fills in skeletons for users from before they were stored.
+/// Clashing lookalikes are left without one and logged.
+pub async fn backfill_skeletons(pool: &Pool<MySql>) -> sqlx::Result<()> {
+    let users = sqlx::query!("SELECT user_id, username FROM users WHERE username_skeleton IS NULL")
+        .fetch_all(pool).await?;
+    for u in users {
+        let skeleton = username_skeleton(&normalize_username(&u.username));
+        if let Err(e) = sqlx::query!(
+            "UPDATE users SET username_skeleton = ? WHERE user_id = ?", skeleton, u.user_id
+        ).execute(pool).await {
+            log::warn!("username `{}` has no skeleton: {:?}", u.username, e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(violations: Vec<Violation>) -> Vec<&'static str> {
+        violations.into_iter().map(|v| v.code).collect()
+    }
+
+    #[test]
+    fn normalize_username_trims_and_folds_compatibility_forms() {
+        assert_eq!(normalize_username("  bob \t"), "bob");
+        assert_eq!(normalize_username("ｂｏｂ"), "bob");
+        assert_eq!(normalize_username("ﬁsh"), "fish");
+    }
+
+    #[test]
+    fn lookalike_usernames_share_a_skeleton() {
+        assert_eq!(username_skeleton("Bob"), username_skeleton("bob"));
+        // Cyrillic о
+        assert_eq!(username_skeleton("b\u{043e}b"), username_skeleton("bob"));
+        assert_ne!(username_skeleton("bob"), username_skeleton("rob"));
+    }
+
+    #[test]
+    fn check_username_accepts_ordinary_names() {
+        assert!(check_username("alice_01").is_empty());
+        assert!(check_username("jean-luc.p").is_empty());
+    }
+
+    #[test]
+    fn check_username_reports_each_rule() {
+        assert_eq!(codes(check_username("ab")), ["too_short"]);
+        assert_eq!(codes(check_username(&"a".repeat(33))), ["too_long"]);
+        assert_eq!(codes(check_username("bob smith")), ["invalid_characters"]);
+        assert_eq!(codes(check_username("Admin")), ["reserved"]);
+        assert_eq!(codes(check_username("\u{0430}dmin")), ["reserved"]);
+    }
+
+    #[test]
+    fn check_password_accepts_reasonable_passwords() {
+        assert!(check_password("correct horse battery", "alice").is_empty());
+    }
+
+    #[test]
+    fn check_password_reports_each_rule() {
+        assert_eq!(codes(check_password("abc123", "alice")), ["too_short"]);
+        assert_eq!(codes(check_password(&"ab1".repeat(400), "alice")), ["too_long"]);
+        assert_eq!(codes(check_password("myALICEpassword", "alice")), ["contains_username"]);
+        assert_eq!(codes(check_password("aaaabbbb", "alice")), ["too_simple"]);
+    }
+
+    #[test]
+    fn check_email_catches_obvious_mistakes() {
+        assert!(check_email("alice@example.com").is_empty());
+        assert!(check_email("a.b+c@mail.example.org").is_empty());
+        for bad in [
+            "alice", "@example.com", "alice@example", "alice@.com", "alice@example.", "al ice@example.com"
+        ] {
+            assert_eq!(codes(check_email(bad)), ["invalid"], "{}", bad);
+        }
+        assert_eq!(codes(check_email(&format!("{}@example.com", "a".repeat(250)))), ["invalid"]);
+    }
+}